@@ -0,0 +1,69 @@
+# Test program for the ELF loader. Prints a greeting and then every argument on its own line,
+# exits with the number of arguments printed.
+#
+# Rebuild with:
+#   as programs/hello.s -o hello.o
+#   ld -static -nostdlib -z max-page-size=0x1000 -z noexecstack -Ttext-segment=0x500000000000 hello.o -o programs/hello
+#   strip programs/hello
+
+.intel_syntax noprefix
+.globl _start
+
+.section .text
+_start:
+    mov r12, [rsp]              # argc
+    lea r13, [rsp + 8]          # argv
+    cmp qword ptr [rip + printed], 0
+    jne bss_not_zeroed
+
+    mov eax, 0                  # write(greeting)
+    lea rdi, [rip + greeting]
+    mov esi, greeting_end - greeting
+    int 0x80
+
+    xor r14d, r14d
+next_arg:
+    cmp r14, r12
+    jae done
+    mov rdi, [r13 + r14 * 8]
+    xor esi, esi
+strlen:
+    cmp byte ptr [rdi + rsi], 0
+    je print
+    inc rsi
+    jmp strlen
+print:
+    mov eax, 0                  # write(argv[i])
+    int 0x80
+    mov eax, 0                  # write("\n")
+    lea rdi, [rip + newline]
+    mov esi, 1
+    int 0x80
+    inc qword ptr [rip + printed]
+    inc r14
+    jmp next_arg
+
+done:
+    mov rdi, [rip + printed]
+    mov eax, 1                  # exit(printed)
+    int 0x80
+    ud2
+
+bss_not_zeroed:
+    mov edi, -1
+    mov eax, 1                  # exit(-1)
+    int 0x80
+    ud2
+
+.section .rodata
+newline:
+    .ascii "\n"
+
+.section .data
+greeting:
+    .ascii "hello from elf\n"
+greeting_end:
+
+.section .bss
+printed:
+    .skip 8
@@ -0,0 +1,15 @@
+# Test program that never exits on its own, it has to be killed.
+#
+# Rebuild with:
+#   as programs/spin.s -o spin.o
+#   ld -static -nostdlib -z max-page-size=0x1000 -z noexecstack -Ttext-segment=0x500000000000 spin.o -o programs/spin
+#   strip programs/spin
+
+.intel_syntax noprefix
+.globl _start
+
+.section .text
+_start:
+    mov eax, 3                  # yield()
+    int 0x80
+    jmp _start
@@ -0,0 +1,126 @@
+use crate::executor::{self, Task};
+use crate::io::{reboot, KeyCode, Modifiers};
+use crate::sync::IrqSafeMutex;
+use crate::{graphics, render, warn};
+use alloc::vec::Vec;
+
+/// A key together with the modifiers that have to be held for it. Modifiers that aren't asked for must be released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkey {
+    pub code: KeyCode,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+impl Hotkey {
+    pub const fn new(code: KeyCode) -> Self {
+        Self {
+            code,
+            ctrl: false,
+            alt: false,
+            shift: false,
+        }
+    }
+
+    pub const fn ctrl(mut self) -> Self {
+        self.ctrl = true;
+        self
+    }
+
+    pub const fn alt(mut self) -> Self {
+        self.alt = true;
+        self
+    }
+
+    pub const fn shift(mut self) -> Self {
+        self.shift = true;
+        self
+    }
+
+    pub fn matches(&self, code: KeyCode, modifiers: &Modifiers) -> bool {
+        self.code == code
+            && self.ctrl == modifiers.is_ctrl()
+            && self.alt == modifiers.is_alt()
+            && self.shift == modifiers.is_shifted()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum HotkeyAction {
+    /// Runs on whatever processed the key press - `keyboard_task` or a `KeyboardStream` - once the keyboard state is
+    /// unlocked again
+    Callback(fn()),
+    /// The task is spawned on the global executor, or dropped with a warning if there is none
+    Task(fn() -> Task),
+}
+
+static HOTKEYS: IrqSafeMutex<Vec<(Hotkey, HotkeyAction)>> =
+    IrqSafeMutex::new("io::hotkeys::HOTKEYS", Vec::new());
+
+/// Binds `action` to `hotkey`, replacing what was bound to it before
+pub fn register_hotkey(hotkey: Hotkey, action: HotkeyAction) {
+    let mut hotkeys = HOTKEYS.lock();
+    match hotkeys.iter_mut().find(|(bound, _)| *bound == hotkey) {
+        Some(binding) => binding.1 = action,
+        None => hotkeys.push((hotkey, action)),
+    }
+}
+
+/// Returns false if nothing was bound to `hotkey`
+pub fn unregister_hotkey(hotkey: Hotkey) -> bool {
+    let mut hotkeys = HOTKEYS.lock();
+    let len = hotkeys.len();
+    hotkeys.retain(|(bound, _)| *bound != hotkey);
+    hotkeys.len() != len
+}
+
+/// The action bound to the pressed key, if any. The keyboard streams don't get the key press when there is one.
+pub(crate) fn find_hotkey(code: KeyCode, modifiers: &Modifiers) -> Option<HotkeyAction> {
+    HOTKEYS
+        .lock()
+        .iter()
+        .find(|(hotkey, _)| hotkey.matches(code, modifiers))
+        .map(|(_, action)| *action)
+}
+
+/// Called without any keyboard lock held, so callbacks can look at the keyboard themselves
+pub(crate) fn run_hotkey_action(action: HotkeyAction) {
+    match action {
+        HotkeyAction::Callback(callback) => callback(),
+        HotkeyAction::Task(task) => {
+            if executor::try_spawn(task()).is_none() {
+                warn!("no executor to run the hotkey's task on, dropping it");
+            }
+        }
+    }
+}
+
+fn reboot_callback() {
+    reboot()
+}
+
+fn screenshot_task() -> Task {
+    Task::new(graphics::send_screenshot()).with_name("screenshot")
+}
+
+/// Ctrl+Alt+Delete reboots, PrintScreen sends a screenshot over serial, Ctrl+Alt+W toggles the renderer's wireframe
+/// mode and Ctrl+Alt+T prints the executor's task table
+pub fn register_builtin_hotkeys() {
+    register_hotkey(
+        Hotkey::new(KeyCode::Delete).ctrl().alt(),
+        HotkeyAction::Callback(reboot_callback),
+    );
+    register_hotkey(
+        Hotkey::new(KeyCode::PrintScreen),
+        HotkeyAction::Task(screenshot_task),
+    );
+    register_hotkey(
+        Hotkey::new(KeyCode::W).ctrl().alt(),
+        HotkeyAction::Callback(render::toggle_wireframe),
+    );
+    register_hotkey(
+        Hotkey::new(KeyCode::T).ctrl().alt(),
+        HotkeyAction::Callback(executor::request_dump),
+    );
+}
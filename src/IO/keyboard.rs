@@ -1,4 +1,7 @@
-use crate::serial_println;
+use crate::io::keyboard_stream::wake_keyboard_streams;
+use crate::io::layouts::{DeadKey, KeyboardLayout, Us104Key};
+use crate::io::ps2::Leds;
+use crate::warn;
 use conquer_once::spin::OnceCell;
 use crossbeam_queue::ArrayQueue;
 
@@ -9,14 +12,15 @@ pub static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 pub fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if queue.push(scancode).is_err() {
-            serial_println!("WARNING: scancode queue full; dropping keyboard input");
+            warn!("scancode queue full; dropping keyboard input");
         }
+        wake_keyboard_streams();
     } else {
-        serial_println!("WARNING: scancode queue uninitialized");
+        warn!("scancode queue uninitialized");
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KeyCode {
     AltLeft = 0,
     AltRight = 1,
@@ -124,13 +128,14 @@ pub enum KeyCode {
     X = 112,
     Y = 113,
     Z = 114,
+    Oem102 = 115,
 }
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum KeyState {
     Up,
     Down,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct KeyEvent {
     pub code: KeyCode,
     pub state: KeyState,
@@ -143,6 +148,7 @@ impl KeyEvent {
     }
 }
 //DecodedKey useful for displaying
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DecodedKey {
     RawKey(KeyCode),
     Unicode(char),
@@ -248,50 +254,207 @@ fn get_key_code(code: u8) -> Result<KeyCode, ()> {
         0x51 => Ok(KeyCode::Numpad3),            // 51
         0x52 => Ok(KeyCode::Numpad0),            // 52
         0x53 => Ok(KeyCode::NumpadPeriod),       // 53
-        //0x54
+        0x54 => Ok(KeyCode::SysReq),             // 54, Alt + PrintScreen
         //0x55
-        // 0x56
-        0x57 => Ok(KeyCode::F11), // 57
-        0x58 => Ok(KeyCode::F12), // 58
+        0x56 => Ok(KeyCode::Oem102), // 56, next to left shift on ISO keyboards
+        0x57 => Ok(KeyCode::F11),    // 57
+        0x58 => Ok(KeyCode::F12),    // 58
         0x81..=0xD8 => Ok(get_key_code(code - 0x80)?),
         _ => Err(()),
     }
 }
 
-pub struct Keyboard {
-    lshift: bool,
-    rshift: bool,
-    lctrl: bool,
-    rctrl: bool,
-    numlock: bool,
-    capslock: bool,
-    alt_gr: bool,
+//codes following a 0xE0 prefix
+fn get_extended_key_code(code: u8) -> Result<KeyCode, ()> {
+    match code {
+        0x1C => Ok(KeyCode::NumpadEnter),  // E0 1C
+        0x1D => Ok(KeyCode::ControlRight), // E0 1D
+        0x35 => Ok(KeyCode::NumpadSlash),  // E0 35
+        0x37 => Ok(KeyCode::PrintScreen),  // E0 37
+        0x38 => Ok(KeyCode::AltRight),     // E0 38
+        0x46 => Ok(KeyCode::Break),        // E0 46, Ctrl + Pause
+        0x47 => Ok(KeyCode::Home),         // E0 47
+        0x48 => Ok(KeyCode::ArrowUp),      // E0 48
+        0x49 => Ok(KeyCode::PageUp),       // E0 49
+        0x4B => Ok(KeyCode::ArrowLeft),    // E0 4B
+        0x4D => Ok(KeyCode::ArrowRight),   // E0 4D
+        0x4F => Ok(KeyCode::End),          // E0 4F
+        0x50 => Ok(KeyCode::ArrowDown),    // E0 50
+        0x51 => Ok(KeyCode::PageDown),     // E0 51
+        0x52 => Ok(KeyCode::Insert),       // E0 52
+        0x53 => Ok(KeyCode::Delete),       // E0 53
+        0x5B => Ok(KeyCode::WindowsLeft),  // E0 5B
+        0x5C => Ok(KeyCode::WindowsRight), // E0 5C
+        0x5D => Ok(KeyCode::Menus),        // E0 5D
+        _ => Err(()),
+    }
 }
 
-impl Keyboard {
-    pub fn new() -> Self {
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DecodeState {
+    Start,
+    //got 0xE0
+    Extended,
+    //got 0xE1, Pause sends E1 1D 45 E1 9D C5 without a separate release
+    Pause,
+    //got 0xE1 and the second byte
+    PauseSecond(u8),
+}
+
+/// Turns scancode set 1 bytes into `KeyEvent`s, keeping track of the 0xE0 and 0xE1 prefixes in between bytes.
+pub struct ScancodeDecoder {
+    state: DecodeState,
+}
+
+impl ScancodeDecoder {
+    pub const fn new() -> Self {
         Self {
-            lshift: false,
-            rshift: false,
-            lctrl: false,
-            rctrl: false,
-            numlock: false,
-            capslock: false,
-            alt_gr: false,
+            state: DecodeState::Start,
+        }
+    }
+
+    /// Returns the event once a sequence is complete, None while in the middle of one or for bytes that
+    /// aren't keys on their own (the fake shifts around PrintScreen). Unknown sequences are an error and start over.
+    pub fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, ()> {
+        match (self.state, byte) {
+            (DecodeState::Start, 0xE0) => {
+                self.state = DecodeState::Extended;
+                Ok(None)
+            }
+            (DecodeState::Start, 0xE1) => {
+                self.state = DecodeState::Pause;
+                Ok(None)
+            }
+            (DecodeState::Start, code) => get_key_ev(code).map(Some),
+            (DecodeState::Extended, code) => {
+                self.state = DecodeState::Start;
+                match code {
+                    //fake shifts sent around PrintScreen and the extended keys while shift or numlock are active
+                    0x2A | 0xAA | 0x36 | 0xB6 => Ok(None),
+                    0x80..=0xFF => Ok(Some(KeyEvent::new(
+                        get_extended_key_code(code - 0x80)?,
+                        KeyState::Up,
+                    ))),
+                    _ => Ok(Some(KeyEvent::new(
+                        get_extended_key_code(code)?,
+                        KeyState::Down,
+                    ))),
+                }
+            }
+            (DecodeState::Pause, code @ (0x1D | 0x9D)) => {
+                self.state = DecodeState::PauseSecond(code);
+                Ok(None)
+            }
+            (DecodeState::PauseSecond(0x1D), 0x45) => {
+                self.state = DecodeState::Start;
+                Ok(Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Down)))
+            }
+            (DecodeState::PauseSecond(0x9D), 0xC5) => {
+                self.state = DecodeState::Start;
+                Ok(Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Up)))
+            }
+            _ => {
+                self.state = DecodeState::Start;
+                Err(())
+            }
         }
     }
+}
 
-    const fn is_shifted(&self) -> bool {
+impl Default for ScancodeDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The state of the modifier and lock keys, as the layouts see it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Modifiers {
+    pub lshift: bool,
+    pub rshift: bool,
+    pub lctrl: bool,
+    pub rctrl: bool,
+    pub lalt: bool,
+    pub numlock: bool,
+    pub capslock: bool,
+    pub scrolllock: bool,
+    pub alt_gr: bool,
+}
+
+impl Modifiers {
+    pub const fn is_shifted(&self) -> bool {
         self.lshift | self.rshift
     }
 
-    const fn is_ctrl(&self) -> bool {
+    pub const fn is_ctrl(&self) -> bool {
         self.lctrl | self.rctrl
     }
 
-    const fn is_caps(&self) -> bool {
+    /// Either alt key, AltGr counts too
+    pub const fn is_alt(&self) -> bool {
+        self.lalt | self.alt_gr
+    }
+
+    pub const fn is_caps(&self) -> bool {
         (self.lshift | self.rshift) ^ self.capslock
     }
+}
+
+pub struct Keyboard {
+    modifiers: Modifiers,
+    layout: &'static dyn KeyboardLayout,
+    //accent waiting for the next key
+    dead_key: Option<DeadKey>,
+    //a lock key changed the LEDs since the last take_led_update
+    leds_changed: bool,
+    //second character of a key press, see take_pending_key
+    pending_key: Option<DecodedKey>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self {
+            modifiers: Modifiers::default(),
+            layout: &Us104Key,
+            dead_key: None,
+            leds_changed: false,
+            pending_key: None,
+        }
+    }
+
+    /// Switches the layout, a pending dead key is dropped
+    pub fn set_layout(&mut self, layout: &'static dyn KeyboardLayout) {
+        self.layout = layout;
+        self.dead_key = None;
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn leds(&self) -> Leds {
+        let mut leds = Leds::empty();
+        leds.set(Leds::CAPS_LOCK, self.modifiers.capslock);
+        leds.set(Leds::NUM_LOCK, self.modifiers.numlock);
+        leds.set(Leds::SCROLL_LOCK, self.modifiers.scrolllock);
+        leds
+    }
+
+    /// A key press can give two characters: a dead key followed by a letter it has no accented form for gives the
+    /// accent from `process_key_ev` and the letter from here.
+    pub fn take_pending_key(&mut self) -> Option<DecodedKey> {
+        self.pending_key.take()
+    }
+
+    /// The LEDs to show if a lock key changed them since the last call. Sending them to the keyboard is left to
+    /// the caller, so processing a key never waits for the controller.
+    pub fn take_led_update(&mut self) -> Option<Leds> {
+        if core::mem::take(&mut self.leds_changed) {
+            Some(self.leds())
+        } else {
+            None
+        }
+    }
 
     pub fn process_key_ev(&mut self, ev: KeyEvent) -> Option<DecodedKey> {
         //Modifier Keys update the field of the struct - every other key returns DecodedKey from map_keycode function
@@ -300,562 +463,145 @@ impl Keyboard {
                 code: KeyCode::ShiftLeft,
                 state: KeyState::Down,
             } => {
-                self.lshift = true;
+                self.modifiers.lshift = true;
                 None
             }
             KeyEvent {
                 code: KeyCode::ShiftRight,
                 state: KeyState::Down,
             } => {
-                self.rshift = true;
+                self.modifiers.rshift = true;
                 None
             }
             KeyEvent {
                 code: KeyCode::ShiftLeft,
                 state: KeyState::Up,
             } => {
-                self.lshift = false;
+                self.modifiers.lshift = false;
                 None
             }
             KeyEvent {
                 code: KeyCode::ShiftRight,
                 state: KeyState::Up,
             } => {
-                self.rshift = false;
+                self.modifiers.rshift = false;
                 None
             }
             KeyEvent {
                 code: KeyCode::CapsLock,
                 state: KeyState::Down,
             } => {
-                self.capslock = !self.capslock;
+                self.modifiers.capslock = !self.modifiers.capslock;
+                self.leds_changed = true;
                 None
             }
             KeyEvent {
                 code: KeyCode::NumpadLock,
                 state: KeyState::Down,
             } => {
-                self.numlock = !self.numlock;
+                self.modifiers.numlock = !self.modifiers.numlock;
+                self.leds_changed = true;
+                None
+            }
+            KeyEvent {
+                code: KeyCode::ScrollLock,
+                state: KeyState::Down,
+            } => {
+                self.modifiers.scrolllock = !self.modifiers.scrolllock;
+                self.leds_changed = true;
                 None
             }
             KeyEvent {
                 code: KeyCode::ControlLeft,
                 state: KeyState::Down,
             } => {
-                self.lctrl = true;
+                self.modifiers.lctrl = true;
                 None
             }
             KeyEvent {
                 code: KeyCode::ControlLeft,
                 state: KeyState::Up,
             } => {
-                self.lctrl = false;
+                self.modifiers.lctrl = false;
                 None
             }
             KeyEvent {
                 code: KeyCode::ControlRight,
                 state: KeyState::Down,
             } => {
-                self.rctrl = true;
+                self.modifiers.rctrl = true;
                 None
             }
             KeyEvent {
                 code: KeyCode::ControlRight,
                 state: KeyState::Up,
             } => {
-                self.rctrl = false;
+                self.modifiers.rctrl = false;
+                None
+            }
+            KeyEvent {
+                code: KeyCode::AltLeft,
+                state: KeyState::Down,
+            } => {
+                self.modifiers.lalt = true;
+                None
+            }
+            KeyEvent {
+                code: KeyCode::AltLeft,
+                state: KeyState::Up,
+            } => {
+                self.modifiers.lalt = false;
                 None
             }
             KeyEvent {
                 code: KeyCode::AltRight,
                 state: KeyState::Down,
             } => {
-                self.alt_gr = true;
+                self.modifiers.alt_gr = true;
                 None
             }
             KeyEvent {
                 code: KeyCode::AltRight,
                 state: KeyState::Up,
             } => {
-                self.alt_gr = false;
+                self.modifiers.alt_gr = false;
                 None
             }
             KeyEvent {
                 code: c,
                 state: KeyState::Down,
-            } => Some(self.map_keycode(c)),
+            } => self.map_keycode(c),
             _ => None,
         }
     }
 
-    fn map_keycode(&self, code: KeyCode) -> DecodedKey {
-        //US 104
-        match code {
-            KeyCode::BackTick => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('~')
-                } else {
-                    DecodedKey::Unicode('`')
-                }
-            }
-            KeyCode::Escape => DecodedKey::Unicode(0x1B.into()),
-            KeyCode::Key1 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('!')
-                } else {
-                    DecodedKey::Unicode('1')
-                }
-            }
-            KeyCode::Key2 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('@')
-                } else {
-                    DecodedKey::Unicode('2')
-                }
-            }
-            KeyCode::Key3 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('#')
-                } else {
-                    DecodedKey::Unicode('3')
-                }
-            }
-            KeyCode::Key4 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('$')
-                } else {
-                    DecodedKey::Unicode('4')
-                }
-            }
-            KeyCode::Key5 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('%')
-                } else {
-                    DecodedKey::Unicode('5')
-                }
-            }
-            KeyCode::Key6 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('^')
-                } else {
-                    DecodedKey::Unicode('6')
-                }
-            }
-            KeyCode::Key7 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('&')
-                } else {
-                    DecodedKey::Unicode('7')
-                }
-            }
-            KeyCode::Key8 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('*')
-                } else {
-                    DecodedKey::Unicode('8')
-                }
-            }
-            KeyCode::Key9 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('(')
-                } else {
-                    DecodedKey::Unicode('9')
-                }
-            }
-            KeyCode::Key0 => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode(')')
-                } else {
-                    DecodedKey::Unicode('0')
-                }
-            }
-            KeyCode::Minus => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('_')
-                } else {
-                    DecodedKey::Unicode('-')
-                }
-            }
-            KeyCode::Equals => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('+')
-                } else {
-                    DecodedKey::Unicode('=')
-                }
-            }
-            KeyCode::Backspace => DecodedKey::Unicode(0x08.into()),
-            KeyCode::Tab => DecodedKey::Unicode(0x09.into()),
-            KeyCode::Q => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0011}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('Q')
-                } else {
-                    DecodedKey::Unicode('q')
-                }
-            }
-            KeyCode::W => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0017}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('W')
-                } else {
-                    DecodedKey::Unicode('w')
-                }
-            }
-            KeyCode::E => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0005}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('E')
-                } else {
-                    DecodedKey::Unicode('e')
-                }
-            }
-            KeyCode::R => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0012}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('R')
-                } else {
-                    DecodedKey::Unicode('r')
-                }
-            }
-            KeyCode::T => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0014}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('T')
-                } else {
-                    DecodedKey::Unicode('t')
-                }
-            }
-            KeyCode::Y => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0019}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('Y')
-                } else {
-                    DecodedKey::Unicode('y')
-                }
-            }
-            KeyCode::U => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0015}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('U')
-                } else {
-                    DecodedKey::Unicode('u')
-                }
-            }
-            KeyCode::I => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0009}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('I')
-                } else {
-                    DecodedKey::Unicode('i')
-                }
-            }
-            KeyCode::O => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{000F}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('O')
-                } else {
-                    DecodedKey::Unicode('o')
-                }
-            }
-            KeyCode::P => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0010}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('P')
-                } else {
-                    DecodedKey::Unicode('p')
-                }
-            }
-            KeyCode::BracketSquareLeft => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('{')
-                } else {
-                    DecodedKey::Unicode('[')
-                }
-            }
-            KeyCode::BracketSquareRight => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('}')
-                } else {
-                    DecodedKey::Unicode(']')
-                }
-            }
-            KeyCode::BackSlash => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('|')
-                } else {
-                    DecodedKey::Unicode('\\')
-                }
-            }
-            KeyCode::A => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0001}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('A')
-                } else {
-                    DecodedKey::Unicode('a')
-                }
-            }
-            KeyCode::S => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0013}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('S')
-                } else {
-                    DecodedKey::Unicode('s')
-                }
-            }
-            KeyCode::D => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0004}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('D')
-                } else {
-                    DecodedKey::Unicode('d')
-                }
-            }
-            KeyCode::F => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0006}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('F')
-                } else {
-                    DecodedKey::Unicode('f')
-                }
-            }
-            KeyCode::G => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0007}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('G')
-                } else {
-                    DecodedKey::Unicode('g')
-                }
-            }
-            KeyCode::H => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0008}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('H')
-                } else {
-                    DecodedKey::Unicode('h')
-                }
-            }
-            KeyCode::J => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{000A}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('J')
-                } else {
-                    DecodedKey::Unicode('j')
-                }
-            }
-            KeyCode::K => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{000B}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('K')
-                } else {
-                    DecodedKey::Unicode('k')
-                }
-            }
-            KeyCode::L => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{000C}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('L')
-                } else {
-                    DecodedKey::Unicode('l')
-                }
-            }
-            KeyCode::SemiColon => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode(':')
-                } else {
-                    DecodedKey::Unicode(';')
-                }
-            }
-            KeyCode::Quote => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('"')
-                } else {
-                    DecodedKey::Unicode('\'')
-                }
-            }
-            // Enter gives LF, not CRLF or CR
-            KeyCode::Enter => DecodedKey::Unicode(10.into()),
-            KeyCode::Z => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{001A}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('Z')
-                } else {
-                    DecodedKey::Unicode('z')
-                }
-            }
-            KeyCode::X => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0018}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('X')
-                } else {
-                    DecodedKey::Unicode('x')
-                }
-            }
-            KeyCode::C => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0003}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('C')
-                } else {
-                    DecodedKey::Unicode('c')
-                }
-            }
-            KeyCode::V => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0016}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('V')
-                } else {
-                    DecodedKey::Unicode('v')
-                }
-            }
-            KeyCode::B => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{0002}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('B')
-                } else {
-                    DecodedKey::Unicode('b')
-                }
-            }
-            KeyCode::N => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{000E}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('N')
-                } else {
-                    DecodedKey::Unicode('n')
-                }
-            }
-            KeyCode::M => {
-                if self.is_ctrl() {
-                    DecodedKey::Unicode('\u{000D}')
-                } else if self.is_caps() {
-                    DecodedKey::Unicode('M')
-                } else {
-                    DecodedKey::Unicode('m')
-                }
-            }
-            KeyCode::Comma => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('<')
-                } else {
-                    DecodedKey::Unicode(',')
-                }
-            }
-            KeyCode::Fullstop => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('>')
-                } else {
-                    DecodedKey::Unicode('.')
-                }
-            }
-            KeyCode::Slash => {
-                if self.is_shifted() {
-                    DecodedKey::Unicode('?')
-                } else {
-                    DecodedKey::Unicode('/')
-                }
-            }
-            KeyCode::Spacebar => DecodedKey::Unicode(' '),
-            KeyCode::Delete => DecodedKey::Unicode(127.into()),
-            KeyCode::NumpadSlash => DecodedKey::Unicode('/'),
-            KeyCode::NumpadStar => DecodedKey::Unicode('*'),
-            KeyCode::NumpadMinus => DecodedKey::Unicode('-'),
-            KeyCode::Numpad7 => {
-                if self.numlock {
-                    DecodedKey::Unicode('7')
-                } else {
-                    DecodedKey::RawKey(KeyCode::Home)
-                }
-            }
-            KeyCode::Numpad8 => {
-                if self.numlock {
-                    DecodedKey::Unicode('8')
-                } else {
-                    DecodedKey::RawKey(KeyCode::ArrowUp)
-                }
-            }
-            KeyCode::Numpad9 => {
-                if self.numlock {
-                    DecodedKey::Unicode('9')
-                } else {
-                    DecodedKey::RawKey(KeyCode::PageUp)
-                }
-            }
-            KeyCode::NumpadPlus => DecodedKey::Unicode('+'),
-            KeyCode::Numpad4 => {
-                if self.numlock {
-                    DecodedKey::Unicode('4')
-                } else {
-                    DecodedKey::RawKey(KeyCode::ArrowLeft)
-                }
-            }
-            KeyCode::Numpad5 => DecodedKey::Unicode('5'),
-            KeyCode::Numpad6 => {
-                if self.numlock {
-                    DecodedKey::Unicode('6')
-                } else {
-                    DecodedKey::RawKey(KeyCode::ArrowRight)
-                }
-            }
-            KeyCode::Numpad1 => {
-                if self.numlock {
-                    DecodedKey::Unicode('1')
-                } else {
-                    DecodedKey::RawKey(KeyCode::End)
-                }
-            }
-            KeyCode::Numpad2 => {
-                if self.numlock {
-                    DecodedKey::Unicode('2')
-                } else {
-                    DecodedKey::RawKey(KeyCode::ArrowDown)
-                }
-            }
-            KeyCode::Numpad3 => {
-                if self.numlock {
-                    DecodedKey::Unicode('3')
-                } else {
-                    DecodedKey::RawKey(KeyCode::PageDown)
-                }
-            }
-            KeyCode::Numpad0 => {
-                if self.numlock {
-                    DecodedKey::Unicode('0')
-                } else {
-                    DecodedKey::RawKey(KeyCode::Insert)
-                }
-            }
-            KeyCode::NumpadPeriod => {
-                if self.numlock {
-                    DecodedKey::Unicode('.')
-                } else {
-                    DecodedKey::Unicode(127.into())
-                }
-            }
-            KeyCode::NumpadEnter => DecodedKey::Unicode(10.into()),
-            k => DecodedKey::RawKey(k),
+    //dead keys are held back until the next key, which gets the accent if there is a precomposed character for it.
+    //Space or the dead key again give the accent itself, anything else drops it.
+    fn map_keycode(&mut self, code: KeyCode) -> Option<DecodedKey> {
+        if let Some(dead_key) = self.layout.dead_key(code, &self.modifiers) {
+            return match self.dead_key.take() {
+                Some(pending) if pending == dead_key => {
+                    Some(DecodedKey::Unicode(dead_key.as_char()))
+                }
+                _ => {
+                    self.dead_key = Some(dead_key);
+                    None
+                }
+            };
+        }
+        let key = self.layout.map_keycode(code, &self.modifiers);
+        match (self.dead_key.take(), key) {
+            (Some(dead_key), DecodedKey::Unicode(' ')) => {
+                Some(DecodedKey::Unicode(dead_key.as_char()))
+            }
+            (Some(dead_key), DecodedKey::Unicode(c)) => match dead_key.combine(c) {
+                Some(combined) => Some(DecodedKey::Unicode(combined)),
+                None => {
+                    self.pending_key = Some(DecodedKey::Unicode(c));
+                    Some(DecodedKey::Unicode(dead_key.as_char()))
+                }
+            },
+            (_, key) => Some(key),
         }
     }
 }
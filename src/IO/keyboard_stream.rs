@@ -0,0 +1,354 @@
+use crate::io::hotkeys::{find_hotkey, run_hotkey_action, HotkeyAction};
+use crate::io::{
+    set_leds, DecodedKey, KeyCode, KeyEvent, KeyState, Keyboard, KeyboardLayout, Leds, Modifiers,
+    ScancodeDecoder, SCANCODE_QUEUE,
+};
+use crate::sync::IrqSafeMutex;
+use crate::{timer, warn};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use conquer_once::spin::Lazy;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+
+//events a subscriber can fall behind before new ones are dropped for it
+const SUBSCRIBER_CAPACITY: usize = 64;
+
+/// A key event with everything a consumer needs to act on it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyboardEvent {
+    pub event: KeyEvent,
+    /// the modifiers after this event was applied
+    pub modifiers: Modifiers,
+    /// what the current layout makes of it, None for releases, modifiers and dead keys. A dead key followed by a
+    /// letter it can't accent gives two events for the letter's press, the accent and then the letter.
+    pub key: Option<DecodedKey>,
+    /// generated by the software key repeat instead of a key press
+    pub repeat: bool,
+}
+
+impl KeyboardEvent {
+    pub fn char(&self) -> Option<char> {
+        match self.key {
+            Some(DecodedKey::Unicode(c)) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+/// The keys that are held down, indexed by their `KeyCode`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PressedKeys([u64; 2]);
+
+impl PressedKeys {
+    pub fn contains(&self, code: KeyCode) -> bool {
+        let index = code as usize;
+        self.0[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == [0; 2]
+    }
+
+    fn set(&mut self, code: KeyCode, pressed: bool) {
+        let index = code as usize;
+        if pressed {
+            self.0[index / 64] |= 1 << (index % 64);
+        } else {
+            self.0[index / 64] &= !(1 << (index % 64));
+        }
+    }
+}
+
+/// Software key repeat in timer ticks: the first repeat comes `delay` ticks after the press, the next ones every `interval`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRepeat {
+    pub delay: usize,
+    pub interval: usize,
+}
+
+impl KeyRepeat {
+    /// About 500ms and 18 repeats per second with the default PIT frequency of 18.2Hz
+    pub const DEFAULT: KeyRepeat = KeyRepeat {
+        delay: 9,
+        interval: 1,
+    };
+}
+
+//the shared state all streams are fed from, the scancode queue is only drained while it is locked
+struct Hub {
+    decoder: ScancodeDecoder,
+    keyboard: Keyboard,
+    pressed: PressedKeys,
+    //keys whose press triggered a hotkey, until they are released
+    consumed: PressedKeys,
+    key_repeat: Option<KeyRepeat>,
+    //key being repeated and the tick its next repeat is due
+    repeating: Option<(KeyCode, usize)>,
+    //LED state keyboard_task still has to send
+    leds: Option<Leds>,
+    //hotkeys that were pressed, run by with_hub once the lock is released
+    actions: Vec<HotkeyAction>,
+    subscribers: Vec<(u64, VecDeque<KeyboardEvent>)>,
+}
+
+impl Hub {
+    fn pump(&mut self) {
+        if let Ok(queue) = SCANCODE_QUEUE.try_get() {
+            while let Ok(scancode) = queue.pop() {
+                if let Ok(Some(event)) = self.decoder.add_byte(scancode) {
+                    self.handle(event);
+                }
+            }
+        }
+        let now = timer::ticks();
+        //one repeat at most, however long nobody pumped - missed ones aren't made up for
+        if let Some((code, _)) = self.repeating.filter(|&(_, due)| now >= due) {
+            let interval = self.key_repeat.map_or(1, |repeat| repeat.interval.max(1));
+            self.repeating = Some((code, now + interval));
+            let event = KeyEvent::new(code, KeyState::Down);
+            let key = self.keyboard.process_key_ev(event);
+            self.broadcast(event, key, true);
+            if let Some(key) = self.keyboard.take_pending_key() {
+                self.broadcast(event, Some(key), true);
+            }
+        }
+        NEXT_REPEAT.store(
+            self.repeating.map_or(usize::MAX, |(_, due)| due),
+            Ordering::Relaxed,
+        );
+        if let Some(leds) = self.keyboard.take_led_update() {
+            self.leds = Some(leds);
+            TASK_WAKER.wake();
+        }
+    }
+
+    fn handle(&mut self, event: KeyEvent) {
+        let pressed = event.state == KeyState::Down;
+        //the hardware repeat is replaced by the software one
+        if pressed && (self.pressed.contains(event.code) || self.consumed.contains(event.code)) {
+            return;
+        }
+        //nobody saw the press, so nobody gets the release
+        if !pressed && self.consumed.contains(event.code) {
+            self.consumed.set(event.code, false);
+            return;
+        }
+        //hotkeys come before everyone else, they aren't repeated and don't count as pressed
+        let hotkey = if pressed {
+            find_hotkey(event.code, &self.keyboard.modifiers())
+        } else {
+            None
+        };
+        if let Some(action) = hotkey {
+            self.actions.push(action);
+            self.consumed.set(event.code, true);
+            self.repeating = None;
+            return;
+        }
+        self.pressed.set(event.code, pressed);
+        if pressed {
+            self.repeating = match self.key_repeat {
+                Some(repeat) if !is_modifier(event.code) => {
+                    Some((event.code, timer::ticks() + repeat.delay))
+                }
+                _ => None,
+            };
+        } else if matches!(self.repeating, Some((code, _)) if code == event.code) {
+            self.repeating = None;
+        }
+        let key = self.keyboard.process_key_ev(event);
+        let pending = self.keyboard.take_pending_key();
+        self.broadcast(event, key, false);
+        if pending.is_some() {
+            self.broadcast(event, pending, false);
+        }
+    }
+
+    fn broadcast(&mut self, event: KeyEvent, key: Option<DecodedKey>, repeat: bool) {
+        let event = KeyboardEvent {
+            event,
+            modifiers: self.keyboard.modifiers(),
+            key,
+            repeat,
+        };
+        let mut dropped = false;
+        for (_, queue) in self.subscribers.iter_mut() {
+            if queue.len() < SUBSCRIBER_CAPACITY {
+                queue.push_back(event);
+            } else {
+                dropped = true;
+            }
+        }
+        if dropped {
+            warn!("keyboard subscriber queue full; dropping key event");
+        }
+    }
+}
+
+//modifiers and lock keys don't repeat
+fn is_modifier(code: KeyCode) -> bool {
+    matches!(
+        code,
+        KeyCode::ShiftLeft
+            | KeyCode::ShiftRight
+            | KeyCode::ControlLeft
+            | KeyCode::ControlRight
+            | KeyCode::AltLeft
+            | KeyCode::AltRight
+            | KeyCode::WindowsLeft
+            | KeyCode::WindowsRight
+            | KeyCode::CapsLock
+            | KeyCode::NumpadLock
+            | KeyCode::ScrollLock
+    )
+}
+
+static HUB: Lazy<IrqSafeMutex<Hub>> = Lazy::new(|| {
+    IrqSafeMutex::new(
+        "io::keyboard_stream::HUB",
+        Hub {
+            decoder: ScancodeDecoder::new(),
+            keyboard: Keyboard::new(),
+            pressed: PressedKeys::default(),
+            consumed: PressedKeys::default(),
+            key_repeat: Some(KeyRepeat::DEFAULT),
+            repeating: None,
+            leds: None,
+            actions: Vec::new(),
+            subscribers: Vec::new(),
+        },
+    )
+});
+//kept apart from HUB so the interrupt handlers can wake the streams while it is locked
+static WAKERS: IrqSafeMutex<Vec<Arc<AtomicWaker>>> =
+    IrqSafeMutex::new("io::keyboard_stream::WAKERS", Vec::new());
+//tick the next software repeat is due, usize::MAX if no key is repeating
+static NEXT_REPEAT: AtomicUsize = AtomicUsize::new(usize::MAX);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static TASK_WAKER: AtomicWaker = AtomicWaker::new();
+
+//Locks the hub, feeds it the new scancodes and runs `f` on it. Hotkeys pressed meanwhile run after the lock is
+//released, so they may use the keyboard too.
+fn with_hub<R>(f: impl FnOnce(&mut Hub) -> R) -> R {
+    let mut hub = HUB.lock();
+    hub.pump();
+    let result = f(&mut hub);
+    let actions = core::mem::take(&mut hub.actions);
+    drop(hub);
+    for action in actions {
+        run_hotkey_action(action);
+    }
+    result
+}
+
+/// Runs hotkeys as soon as they are pressed, even while no `KeyboardStream` is polled, and sends LED changes to the
+/// keyboard. Waiting for the controller is left to this task instead of whoever locked the hub when a lock key was
+/// pressed. Spawn it once on the global executor, it never ends.
+pub async fn keyboard_task() {
+    core::future::poll_fn(|cx| {
+        TASK_WAKER.register(cx.waker());
+        if let Some(leds) = with_hub(|hub| hub.leds.take()) {
+            if let Err(err) = set_leds(leds) {
+                warn!("couldn't update the keyboard LEDs: {:?}", err);
+            }
+        }
+        Poll::<()>::Pending
+    })
+    .await
+}
+
+/// Wakes every `KeyboardStream` and `keyboard_task`. Called from the keyboard interrupt handler - must not block or allocate.
+pub fn wake_keyboard_streams() {
+    TASK_WAKER.wake();
+    for waker in WAKERS.lock().iter() {
+        waker.wake();
+    }
+}
+
+/// Called by the timer interrupt handler, wakes the streams once a key repeat is due
+pub fn keyboard_tick() {
+    if timer::ticks() >= NEXT_REPEAT.load(Ordering::Relaxed) {
+        wake_keyboard_streams();
+    }
+}
+
+/// The keys held down right now
+pub fn pressed_keys() -> PressedKeys {
+    with_hub(|hub| hub.pressed)
+}
+
+pub fn is_pressed(code: KeyCode) -> bool {
+    pressed_keys().contains(code)
+}
+
+/// Changes the layout the `KeyboardEvent`s are decoded with
+pub fn set_keyboard_layout(layout: &'static dyn KeyboardLayout) {
+    HUB.lock().keyboard.set_layout(layout);
+}
+
+/// None turns the software key repeat off
+pub fn set_key_repeat(key_repeat: Option<KeyRepeat>) {
+    let mut hub = HUB.lock();
+    hub.key_repeat = key_repeat;
+    hub.repeating = None;
+    NEXT_REPEAT.store(usize::MAX, Ordering::Relaxed);
+}
+
+/// Yields every key event from the moment it was created. Any number of them can exist at once, each gets all events.
+pub struct KeyboardStream {
+    id: u64,
+    waker: Arc<AtomicWaker>,
+}
+
+impl KeyboardStream {
+    pub fn new() -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let waker = Arc::new(AtomicWaker::new());
+        //events from before the subscription are not for this stream
+        with_hub(|hub| hub.subscribers.push((id, VecDeque::new())));
+        WAKERS.lock().push(waker.clone());
+        Self { id, waker }
+    }
+}
+
+impl Default for KeyboardStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for KeyboardStream {
+    type Item = KeyboardEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyboardEvent>> {
+        //registered first, so scancodes arriving while the queue is drained wake it again
+        self.waker.register(cx.waker());
+        let event = with_hub(|hub| {
+            hub.subscribers
+                .iter_mut()
+                .find(|(id, _)| *id == self.id)
+                .and_then(|(_, queue)| queue.pop_front())
+        });
+        match event {
+            Some(event) => {
+                self.waker.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for KeyboardStream {
+    fn drop(&mut self) {
+        HUB.lock().subscribers.retain(|(id, _)| *id != self.id);
+        WAKERS
+            .lock()
+            .retain(|waker| !Arc::ptr_eq(waker, &self.waker));
+    }
+}
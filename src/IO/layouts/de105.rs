@@ -0,0 +1,63 @@
+use crate::io::layouts::{letter, shifted, DeadKey, KeyboardLayout, Us104Key};
+use crate::io::{DecodedKey, KeyCode, Modifiers};
+
+/// German 105 key QWERTZ layout with AltGr. ^ and the ´/` key are dead keys.
+pub struct De105Key;
+
+impl KeyboardLayout for De105Key {
+    fn map_keycode(&self, code: KeyCode, modifiers: &Modifiers) -> DecodedKey {
+        if modifiers.alt_gr {
+            let c = match code {
+                KeyCode::Key2 => '²',
+                KeyCode::Key3 => '³',
+                KeyCode::Key7 => '{',
+                KeyCode::Key8 => '[',
+                KeyCode::Key9 => ']',
+                KeyCode::Key0 => '}',
+                KeyCode::Minus => '\\',
+                KeyCode::Q => '@',
+                KeyCode::E => '€',
+                KeyCode::BracketSquareRight => '~',
+                KeyCode::Oem102 => '|',
+                KeyCode::M => 'µ',
+                _ => return DecodedKey::RawKey(code),
+            };
+            return DecodedKey::Unicode(c);
+        }
+        match code {
+            KeyCode::BackTick => DecodedKey::Unicode('°'),
+            KeyCode::Key2 => shifted(modifiers, '2', '"'),
+            KeyCode::Key3 => shifted(modifiers, '3', '§'),
+            KeyCode::Key6 => shifted(modifiers, '6', '&'),
+            KeyCode::Key7 => shifted(modifiers, '7', '/'),
+            KeyCode::Key8 => shifted(modifiers, '8', '('),
+            KeyCode::Key9 => shifted(modifiers, '9', ')'),
+            KeyCode::Key0 => shifted(modifiers, '0', '='),
+            KeyCode::Minus => shifted(modifiers, 'ß', '?'),
+            KeyCode::Y => letter(modifiers, 'z'),
+            KeyCode::Z => letter(modifiers, 'y'),
+            KeyCode::BracketSquareLeft => letter(modifiers, 'ü'),
+            KeyCode::BracketSquareRight => shifted(modifiers, '+', '*'),
+            KeyCode::SemiColon => letter(modifiers, 'ö'),
+            KeyCode::Quote => letter(modifiers, 'ä'),
+            KeyCode::BackSlash => shifted(modifiers, '#', '\''),
+            KeyCode::Oem102 => shifted(modifiers, '<', '>'),
+            KeyCode::Comma => shifted(modifiers, ',', ';'),
+            KeyCode::Fullstop => shifted(modifiers, '.', ':'),
+            KeyCode::Slash => shifted(modifiers, '-', '_'),
+            KeyCode::NumpadPeriod if modifiers.numlock => DecodedKey::Unicode(','),
+            code => Us104Key.map_keycode(code, modifiers),
+        }
+    }
+
+    fn dead_key(&self, code: KeyCode, modifiers: &Modifiers) -> Option<DeadKey> {
+        match code {
+            KeyCode::BackTick if !modifiers.is_shifted() && !modifiers.alt_gr => {
+                Some(DeadKey::Circumflex)
+            }
+            KeyCode::Equals if modifiers.is_shifted() => Some(DeadKey::Grave),
+            KeyCode::Equals => Some(DeadKey::Acute),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use crate::io::layouts::{letter, shifted, KeyboardLayout, Us104Key};
+use crate::io::{DecodedKey, KeyCode, Modifiers};
+
+/// Dvorak on a US 104 key keyboard, numbers and the keys outside the letter block are as on US 104
+pub struct Dvorak104Key;
+
+impl KeyboardLayout for Dvorak104Key {
+    fn map_keycode(&self, code: KeyCode, modifiers: &Modifiers) -> DecodedKey {
+        match code {
+            KeyCode::Minus => shifted(modifiers, '[', '{'),
+            KeyCode::Equals => shifted(modifiers, ']', '}'),
+            KeyCode::Q => shifted(modifiers, '\'', '"'),
+            KeyCode::W => shifted(modifiers, ',', '<'),
+            KeyCode::E => shifted(modifiers, '.', '>'),
+            KeyCode::R => letter(modifiers, 'p'),
+            KeyCode::T => letter(modifiers, 'y'),
+            KeyCode::Y => letter(modifiers, 'f'),
+            KeyCode::U => letter(modifiers, 'g'),
+            KeyCode::I => letter(modifiers, 'c'),
+            KeyCode::O => letter(modifiers, 'r'),
+            KeyCode::P => letter(modifiers, 'l'),
+            KeyCode::BracketSquareLeft => shifted(modifiers, '/', '?'),
+            KeyCode::BracketSquareRight => shifted(modifiers, '=', '+'),
+            KeyCode::A => letter(modifiers, 'a'),
+            KeyCode::S => letter(modifiers, 'o'),
+            KeyCode::D => letter(modifiers, 'e'),
+            KeyCode::F => letter(modifiers, 'u'),
+            KeyCode::G => letter(modifiers, 'i'),
+            KeyCode::H => letter(modifiers, 'd'),
+            KeyCode::J => letter(modifiers, 'h'),
+            KeyCode::K => letter(modifiers, 't'),
+            KeyCode::L => letter(modifiers, 'n'),
+            KeyCode::SemiColon => letter(modifiers, 's'),
+            KeyCode::Quote => shifted(modifiers, '-', '_'),
+            KeyCode::Z => shifted(modifiers, ';', ':'),
+            KeyCode::X => letter(modifiers, 'q'),
+            KeyCode::C => letter(modifiers, 'j'),
+            KeyCode::V => letter(modifiers, 'k'),
+            KeyCode::B => letter(modifiers, 'x'),
+            KeyCode::N => letter(modifiers, 'b'),
+            KeyCode::M => letter(modifiers, 'm'),
+            KeyCode::Comma => letter(modifiers, 'w'),
+            KeyCode::Fullstop => letter(modifiers, 'v'),
+            KeyCode::Slash => letter(modifiers, 'z'),
+            code => Us104Key.map_keycode(code, modifiers),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+mod de105;
+mod dvorak104;
+mod uk105;
+mod us104;
+
+use crate::io::{DecodedKey, KeyCode, Modifiers};
+
+pub use de105::De105Key;
+pub use dvorak104::Dvorak104Key;
+pub use uk105::Uk105Key;
+pub use us104::Us104Key;
+
+/// Turns key codes into characters
+pub trait KeyboardLayout: Send + Sync {
+    fn map_keycode(&self, code: KeyCode, modifiers: &Modifiers) -> DecodedKey;
+
+    /// The accent if `code` is a dead key with these modifiers. Layouts without dead keys don't need to implement it.
+    fn dead_key(&self, _code: KeyCode, _modifiers: &Modifiers) -> Option<DeadKey> {
+        None
+    }
+}
+
+/// An accent typed before the letter it goes on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadKey {
+    Acute,
+    Grave,
+    Circumflex,
+}
+
+impl DeadKey {
+    /// The accent on its own
+    pub fn as_char(self) -> char {
+        match self {
+            DeadKey::Acute => '´',
+            DeadKey::Grave => '`',
+            DeadKey::Circumflex => '^',
+        }
+    }
+
+    /// The precomposed character of `c` with this accent, if there is one
+    pub fn combine(self, c: char) -> Option<char> {
+        let (plain, accented) = match self {
+            DeadKey::Acute => ("aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+            DeadKey::Grave => ("aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+            DeadKey::Circumflex => ("aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+        };
+        plain
+            .chars()
+            .position(|p| p == c)
+            .and_then(|index| accented.chars().nth(index))
+    }
+}
+
+//letters get control codes with ctrl and follow caps lock
+fn letter(modifiers: &Modifiers, c: char) -> DecodedKey {
+    if modifiers.is_ctrl() && c.is_ascii_lowercase() {
+        DecodedKey::Unicode((c as u8 - b'a' + 1).into())
+    } else if modifiers.is_caps() {
+        DecodedKey::Unicode(c.to_uppercase().next().unwrap_or(c))
+    } else {
+        DecodedKey::Unicode(c)
+    }
+}
+
+fn shifted(modifiers: &Modifiers, normal: char, shifted: char) -> DecodedKey {
+    if modifiers.is_shifted() {
+        DecodedKey::Unicode(shifted)
+    } else {
+        DecodedKey::Unicode(normal)
+    }
+}
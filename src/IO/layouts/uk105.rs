@@ -0,0 +1,21 @@
+use crate::io::layouts::{shifted, KeyboardLayout, Us104Key};
+use crate::io::{DecodedKey, KeyCode, Modifiers};
+
+/// UK 105 key layout, the keys it shares with US 104 are left to that
+pub struct Uk105Key;
+
+impl KeyboardLayout for Uk105Key {
+    fn map_keycode(&self, code: KeyCode, modifiers: &Modifiers) -> DecodedKey {
+        match code {
+            KeyCode::BackTick if modifiers.alt_gr => DecodedKey::Unicode('¦'),
+            KeyCode::BackTick => shifted(modifiers, '`', '¬'),
+            KeyCode::Key2 => shifted(modifiers, '2', '"'),
+            KeyCode::Key3 => shifted(modifiers, '3', '£'),
+            KeyCode::Key4 if modifiers.alt_gr => DecodedKey::Unicode('€'),
+            KeyCode::Quote => shifted(modifiers, '\'', '@'),
+            KeyCode::BackSlash => shifted(modifiers, '#', '~'),
+            KeyCode::Oem102 => shifted(modifiers, '\\', '|'),
+            code => Us104Key.map_keycode(code, modifiers),
+        }
+    }
+}
@@ -1,11 +1,36 @@
+mod hotkeys;
 mod keyboard;
+mod keyboard_stream;
+mod layouts;
 mod mouse;
+mod ps2;
 mod serial;
 
+pub use hotkeys::{
+    register_builtin_hotkeys, register_hotkey, unregister_hotkey, Hotkey, HotkeyAction,
+};
 pub use keyboard::{
-    add_scancode, get_key_ev, KeyCode, KeyEvent, KeyState, Keyboard, SCANCODE_QUEUE,
+    add_scancode, get_key_ev, DecodedKey, KeyCode, KeyEvent, KeyState, Keyboard, Modifiers,
+    ScancodeDecoder, SCANCODE_QUEUE,
+};
+pub use keyboard_stream::{
+    is_pressed, keyboard_task, keyboard_tick, pressed_keys, set_key_repeat, set_keyboard_layout,
+    wake_keyboard_streams, KeyRepeat, KeyboardEvent, KeyboardStream, PressedKeys,
 };
+pub use layouts::{De105Key, DeadKey, Dvorak104Key, KeyboardLayout, Uk105Key, Us104Key};
 
-pub use mouse::init_mouse;
-pub use mouse::MOUSE;
-pub use serial::_print;
+pub use mouse::{init_mouse, set_resolution, set_sample_rate, set_scaling};
+pub use mouse::{
+    Acceleration, Mouse, MouseButton, MouseButtonEvent, MouseKind, MouseState, Resolution,
+    SampleRate, Scaling, MOUSE,
+};
+pub use ps2::{
+    init_keyboard, reboot, reset_device, reset_keyboard, set_leds, set_scancode_set, set_typematic,
+    Controller, Device, Leds, Ps2Error, RepeatDelay, ScancodeSet, CONTROLLER,
+};
+pub use serial::{
+    _print, init_serial, open_port, receive_serial, write_bytes, ComPort, Parity, SerialConfig,
+    SerialPort, SerialReservation, SerialStream, StopBits, WordLength, DEBUG_SERIAL, SERIAL,
+};
+#[cfg(feature = "capture")]
+pub use serial::{start_capture, stop_capture};
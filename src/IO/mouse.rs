@@ -1,45 +1,179 @@
+use crate::io::{reset_device, Controller, Device, Ps2Error, CONTROLLER};
+use crate::sync::IrqSafeMutex;
+use crate::timer;
 use bitflags::bitflags;
 use conquer_once::spin::Lazy;
-use spinning_top::Spinlock;
-use x86_64::instructions::port::Port;
 
-const ADDRESS_PORT_ADDRESS: u16 = 0x64;
-const DATA_PORT_ADDRESS: u16 = 0x60;
-const GET_STATUS_BYTE: u8 = 0x20;
-const SET_STATUS_BYTE: u8 = 0x60;
 const SET_DEFAULTS: u8 = 0xF6;
 const ENABLE_PACKET_STREAMING: u8 = 0xF4;
+const DISABLE_PACKET_STREAMING: u8 = 0xF5;
+const SET_RESOLUTION: u8 = 0xE8;
+const SET_SCALING_1_1: u8 = 0xE6;
+const SET_SCALING_2_1: u8 = 0xE7;
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const GET_DEVICE_ID: u8 = 0xF2;
 
-pub static MOUSE: Lazy<Spinlock<Mouse>> = Lazy::new(|| Spinlock::new(Mouse::new()));
-
-fn send_command(
-    command_port: &mut Port<u8>,
-    data_port: &mut Port<u8>,
-    command: u8,
-) -> Result<(), &'static str> {
-    unsafe {
-        command_port.write(0xD4);
-        data_port.write(command);
-        //0xFA is acknoledgement
-        if data_port.read() != 0xFA {
-            return Err("mouse did not respond to the command");
-        }
-        Ok(())
+//sample rates that unlock the IntelliMouse extensions, each answered with a new device id
+const WHEEL_SEQUENCE: [u8; 3] = [200, 100, 80];
+const FIVE_BUTTON_SEQUENCE: [u8; 3] = [200, 200, 80];
+
+//a packet's bytes arrive within a millisecond, a gap of this many timer ticks means one was lost
+const RESYNC_TICKS: usize = 2;
+
+const BUTTON_EVENT_CAPACITY: usize = 32;
+
+pub static MOUSE: Lazy<IrqSafeMutex<Mouse>> =
+    Lazy::new(|| IrqSafeMutex::new("io::MOUSE", Mouse::new()));
+
+/// Resets and detects the mouse, then turns on its interrupt. Without a working mouse the port is switched off
+/// again and `Mouse::is_present` stays false, so everything else keeps running with a mouse that never moves.
+pub fn init_mouse() -> Result<MouseKind, Ps2Error> {
+    let result = setup();
+    if result.is_err() {
+        let _ = CONTROLLER.lock().disable_second_port();
+    }
+    result
+}
+
+//the controller isn't locked during the reset, which takes up to a second
+fn setup() -> Result<MouseKind, Ps2Error> {
+    CONTROLLER.lock().enable_second_port()?;
+    reset_device(Device::Mouse)?;
+    let mut controller = CONTROLLER.lock();
+    //followed by the device id, which is always 0 after a reset
+    controller.read_from(Device::Mouse)?;
+    controller.send(Device::Mouse, SET_DEFAULTS)?;
+    let kind = detect_kind(&mut controller)?;
+    //detection leaves the sample rate at 80
+    controller.send(Device::Mouse, SET_SAMPLE_RATE)?;
+    controller.send(Device::Mouse, SampleRate::Hz100 as u8)?;
+    //set before the first packet can arrive
+    MOUSE.lock().set_present(kind);
+    controller.send(Device::Mouse, ENABLE_PACKET_STREAMING)?;
+    controller.enable_second_port_interrupt()?;
+    Ok(kind)
+}
+
+/// Packets per second the mouse sends while it is moved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleRate {
+    Hz10 = 10,
+    Hz20 = 20,
+    Hz40 = 40,
+    Hz60 = 60,
+    Hz80 = 80,
+    Hz100 = 100,
+    Hz200 = 200,
+}
+
+/// Counts the mouse reports per millimeter of movement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    CountsPerMm1 = 0,
+    CountsPerMm2 = 1,
+    CountsPerMm4 = 2,
+    CountsPerMm8 = 3,
+}
+
+/// The mouse's own acceleration, 2:1 doubles bigger movements in steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    OneToOne,
+    TwoToOne,
+}
+
+pub fn set_sample_rate(rate: SampleRate) -> Result<(), Ps2Error> {
+    configure(&[SET_SAMPLE_RATE, rate as u8])
+}
+
+pub fn set_resolution(resolution: Resolution) -> Result<(), Ps2Error> {
+    configure(&[SET_RESOLUTION, resolution as u8])
+}
+
+pub fn set_scaling(scaling: Scaling) -> Result<(), Ps2Error> {
+    configure(&[match scaling {
+        Scaling::OneToOne => SET_SCALING_1_1,
+        Scaling::TwoToOne => SET_SCALING_2_1,
+    }])
+}
+
+//sends the bytes with streaming paused, so no packet is mistaken for an ACK
+fn configure(bytes: &[u8]) -> Result<(), Ps2Error> {
+    if !MOUSE.lock().is_present() {
+        return Err(Ps2Error::NoDevice);
     }
+    let mut controller = CONTROLLER.lock();
+    controller.send(Device::Mouse, DISABLE_PACKET_STREAMING)?;
+    let result = bytes
+        .iter()
+        .try_for_each(|byte| controller.send(Device::Mouse, *byte));
+    //a packet cut off by the pause must not be completed with the next one
+    MOUSE.lock().discard_partial_packet();
+    controller.send(Device::Mouse, ENABLE_PACKET_STREAMING)?;
+    result
 }
 
-pub fn init_mouse() {
-    let mut command_port: Port<u8> = Port::new(ADDRESS_PORT_ADDRESS);
-    let mut data_port: Port<u8> = Port::new(DATA_PORT_ADDRESS);
-    unsafe {
-        command_port.write(GET_STATUS_BYTE);
-        let status = data_port.read() | 0x02;
-        command_port.write(SET_STATUS_BYTE);
-        data_port.write(status & 0xDF);
+fn device_id(controller: &mut Controller) -> Result<u8, Ps2Error> {
+    controller.send(Device::Mouse, GET_DEVICE_ID)?;
+    controller.read_from(Device::Mouse)
+}
 
-        send_command(&mut command_port, &mut data_port, SET_DEFAULTS).unwrap();
-        send_command(&mut command_port, &mut data_port, ENABLE_PACKET_STREAMING).unwrap();
+//a mouse only reports the next id after the sample rate sequence of the previous one
+fn detect_kind(controller: &mut Controller) -> Result<MouseKind, Ps2Error> {
+    let mut kind = MouseKind::Standard;
+    for (sequence, next) in [
+        (WHEEL_SEQUENCE, MouseKind::Wheel),
+        (FIVE_BUTTON_SEQUENCE, MouseKind::FiveButton),
+    ] {
+        for rate in sequence {
+            controller.send(Device::Mouse, SET_SAMPLE_RATE)?;
+            controller.send(Device::Mouse, rate)?;
+        }
+        if device_id(controller)? != next as u8 {
+            break;
+        }
+        kind = next;
     }
+    Ok(kind)
+}
+
+/// What the mouse reported as its device id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseKind {
+    /// 3 byte packets, 3 buttons
+    Standard = 0,
+    /// IntelliMouse, a 4th byte with the scroll wheel
+    Wheel = 3,
+    /// IntelliMouse Explorer, the 4th byte also has buttons 4 and 5
+    FiveButton = 4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Button4,
+    Button5,
+}
+
+impl MouseButton {
+    const ALL: [MouseButton; 5] = [
+        MouseButton::Left,
+        MouseButton::Right,
+        MouseButton::Middle,
+        MouseButton::Button4,
+        MouseButton::Button5,
+    ];
+}
+
+/// A button was pressed or released with the cursor at `x`, `y`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseButtonEvent {
+    pub button: MouseButton,
+    pub pressed: bool,
+    pub x: usize,
+    pub y: usize,
 }
 
 bitflags! {
@@ -78,6 +212,9 @@ pub struct MouseState {
     flags: MouseFlags,
     x: i16,
     y: i16,
+    z: i8,
+    button_4: bool,
+    button_5: bool,
 }
 #[allow(dead_code)]
 impl MouseState {
@@ -101,6 +238,27 @@ impl MouseState {
         !self.flags.contains(MouseFlags::RIGHT_BUTTON)
     }
 
+    /// Returns true if the middle mouse button is currently down.
+    pub fn middle_button_down(&self) -> bool {
+        self.flags.contains(MouseFlags::MIDDLE_BUTTON)
+    }
+
+    /// Returns true if the middle mouse button is currently up.
+    pub fn middle_button_up(&self) -> bool {
+        !self.flags.contains(MouseFlags::MIDDLE_BUTTON)
+    }
+
+    /// Returns true if `button` is currently down.
+    pub fn button_down(&self, button: MouseButton) -> bool {
+        match button {
+            MouseButton::Left => self.left_button_down(),
+            MouseButton::Right => self.right_button_down(),
+            MouseButton::Middle => self.middle_button_down(),
+            MouseButton::Button4 => self.button_4,
+            MouseButton::Button5 => self.button_5,
+        }
+    }
+
     /// Returns the x delta of the mouse state.
     pub fn get_x(&self) -> i16 {
         self.x
@@ -110,65 +268,312 @@ impl MouseState {
     pub fn get_y(&self) -> i16 {
         self.y
     }
+
+    /// Returns the scroll wheel delta of the mouse state, negative is up.
+    pub fn get_z(&self) -> i8 {
+        self.z
+    }
+}
+
+/// Software acceleration: per packet, the counts above `threshold` are multiplied by `factor`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Acceleration {
+    pub threshold: f32,
+    pub factor: f32,
+}
+
+impl Acceleration {
+    pub const DEFAULT: Acceleration = Acceleration {
+        threshold: 4.0,
+        factor: 2.0,
+    };
+
+    //the multiplier for a packet with this many counts
+    fn gain(&self, speed: f32) -> f32 {
+        if speed <= self.threshold {
+            1.0
+        } else {
+            1.0 + (self.factor - 1.0) * (speed - self.threshold) / speed
+        }
+    }
+}
+
+//the newest events overwrite the oldest once it's full
+struct ButtonEvents {
+    events: [Option<MouseButtonEvent>; BUTTON_EVENT_CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl ButtonEvents {
+    const fn new() -> Self {
+        Self {
+            events: [None; BUTTON_EVENT_CAPACITY],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, event: MouseButtonEvent) {
+        self.events[(self.start + self.len) % BUTTON_EVENT_CAPACITY] = Some(event);
+        if self.len == BUTTON_EVENT_CAPACITY {
+            self.start = (self.start + 1) % BUTTON_EVENT_CAPACITY;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<MouseButtonEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.start].take();
+        self.start = (self.start + 1) % BUTTON_EVENT_CAPACITY;
+        self.len -= 1;
+        event
+    }
 }
 
 pub struct Mouse {
+    kind: MouseKind,
+    present: bool,
     current_packet: u8,
+    //tick the last packet byte arrived at
+    last_byte: usize,
     current_state: MouseState,
     completed_state: MouseState,
+    //movement since the last get_coords, so packets between two reads aren't lost
+    delta_x: i32,
+    delta_y: i32,
+    //the same movement scaled by the sensitivity and acceleration, for take_motion
+    motion_x: f32,
+    motion_y: f32,
+    sensitivity: f32,
+    acceleration: Option<Acceleration>,
+    scroll: i32,
+    //cursor in screen coordinates, y grows downwards
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    button_events: ButtonEvents,
 }
 
 impl Mouse {
     pub fn new() -> Self {
         Self {
+            kind: MouseKind::Standard,
+            present: false,
             current_packet: 0,
+            last_byte: 0,
             current_state: MouseState::default(),
             completed_state: MouseState::default(),
+            delta_x: 0,
+            delta_y: 0,
+            motion_x: 0.0,
+            motion_y: 0.0,
+            sensitivity: 1.0,
+            acceleration: None,
+            scroll: 0,
+            x: 160,
+            y: 100,
+            width: 320,
+            height: 200,
+            button_events: ButtonEvents::new(),
         }
     }
 
-    pub fn get_coords(&mut self) -> (i16, i16) {
-        let res = (self.completed_state.get_x(), self.completed_state.get_y());
-        self.completed_state = MouseState::default();
+    pub fn kind(&self) -> MouseKind {
+        self.kind
+    }
+
+    /// Sets the packet format, any partially received packet is thrown away
+    pub fn set_kind(&mut self, kind: MouseKind) {
+        self.kind = kind;
+        self.current_packet = 0;
+    }
+
+    /// False until `init_mouse` found a mouse
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    fn set_present(&mut self, kind: MouseKind) {
+        self.set_kind(kind);
+        self.present = true;
+    }
+
+    /// Movement since the last call
+    pub fn get_coords(&mut self) -> (i32, i32) {
+        let res = (self.delta_x, self.delta_y);
+        self.delta_x = 0;
+        self.delta_y = 0;
         res
     }
 
+    /// Movement since the last call with the sensitivity and acceleration applied, y is positive upwards
+    pub fn take_motion(&mut self) -> (f32, f32) {
+        let res = (self.motion_x, self.motion_y);
+        self.motion_x = 0.0;
+        self.motion_y = 0.0;
+        res
+    }
+
+    pub fn sensitivity(&self) -> f32 {
+        self.sensitivity
+    }
+
+    /// Multiplies the movement returned by `take_motion`, 1.0 by default
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity.max(0.0);
+    }
+
+    pub fn acceleration(&self) -> Option<Acceleration> {
+        self.acceleration
+    }
+
+    /// None, the default, turns the software acceleration off
+    pub fn set_acceleration(&mut self, acceleration: Option<Acceleration>) {
+        self.acceleration = acceleration;
+    }
+
+    /// Scroll wheel movement since the last call, negative is up
+    pub fn take_scroll(&mut self) -> i32 {
+        core::mem::take(&mut self.scroll)
+    }
+
+    /// The cursor, always inside the screen
+    pub fn position(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    pub fn set_position(&mut self, x: usize, y: usize) {
+        self.x = x.min(self.width - 1);
+        self.y = y.min(self.height - 1);
+    }
+
+    /// The area the cursor is kept in, 320x200 by default
+    pub fn set_screen_size(&mut self, width: usize, height: usize) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+        self.set_position(self.x, self.y);
+    }
+
+    /// The buttons of the last complete packet
+    pub fn state(&self) -> MouseState {
+        self.completed_state
+    }
+
+    /// The oldest button press or release that wasn't taken yet
+    pub fn pop_button_event(&mut self) -> Option<MouseButtonEvent> {
+        self.button_events.pop()
+    }
+
     pub fn process_packet(&mut self, packet: u8) {
+        self.process_packet_at(packet, timer::ticks());
+    }
+
+    /// Like `process_packet` with the tick the byte arrived at. A packet that stopped for `RESYNC_TICKS` lost a
+    /// byte, it is thrown away and this byte starts a new one.
+    pub fn process_packet_at(&mut self, packet: u8, ticks: usize) {
+        if self.current_packet != 0 && ticks.wrapping_sub(self.last_byte) >= RESYNC_TICKS {
+            self.current_packet = 0;
+        }
+        self.last_byte = ticks;
         match self.current_packet {
             0 => {
                 let flags = MouseFlags::from_bits_truncate(packet);
+                //can't be the first byte of a packet, wait for one that can
                 if !flags.contains(MouseFlags::ALWAYS_ONE) {
                     return;
                 }
                 self.current_state.flags = flags;
             }
             1 => self.process_x_movement(packet),
-            2 => {
-                self.process_y_movement(packet);
-                self.completed_state = self.current_state;
-            }
+            2 => self.process_y_movement(packet),
+            3 => self.process_extension(packet),
             _ => unreachable!(),
         }
-        self.current_packet = (self.current_packet + 1) % 3;
+        self.current_packet += 1;
+        if self.current_packet == self.packet_size() {
+            self.current_packet = 0;
+            self.complete_packet();
+        }
     }
 
-    fn process_x_movement(&mut self, packet: u8) {
-        if !self.current_state.flags.contains(MouseFlags::X_OVERFLOW) {
-            self.current_state.x = if self.current_state.flags.contains(MouseFlags::X_SIGN) {
-                self.sign_extend(packet)
-            } else {
-                packet as i16
-            };
+    fn discard_partial_packet(&mut self) {
+        self.current_packet = 0;
+    }
+
+    fn packet_size(&self) -> u8 {
+        match self.kind {
+            MouseKind::Standard => 3,
+            MouseKind::Wheel | MouseKind::FiveButton => 4,
+        }
+    }
+
+    fn complete_packet(&mut self) {
+        let state = self.current_state;
+        self.delta_x += state.x as i32;
+        self.delta_y += state.y as i32;
+        self.scroll += state.z as i32;
+        let gain = self.acceleration.map_or(1.0, |acceleration| {
+            acceleration.gain((state.x.abs() + state.y.abs()) as f32)
+        }) * self.sensitivity;
+        self.motion_x += state.x as f32 * gain;
+        self.motion_y += state.y as f32 * gain;
+        self.x = (self.x as i32 + state.x as i32).clamp(0, self.width as i32 - 1) as usize;
+        self.y = (self.y as i32 - state.y as i32).clamp(0, self.height as i32 - 1) as usize;
+        for button in MouseButton::ALL {
+            let pressed = state.button_down(button);
+            if pressed != self.completed_state.button_down(button) {
+                self.button_events.push(MouseButtonEvent {
+                    button,
+                    pressed,
+                    x: self.x,
+                    y: self.y,
+                });
+            }
         }
+        self.completed_state = state;
+    }
+
+    //overflowed deltas are too big for 9 bits, they are saturated instead of dropped
+    fn process_x_movement(&mut self, packet: u8) {
+        let flags = self.current_state.flags;
+        self.current_state.x = match (
+            flags.contains(MouseFlags::X_OVERFLOW),
+            flags.contains(MouseFlags::X_SIGN),
+        ) {
+            (true, true) => -256,
+            (true, false) => 255,
+            (false, true) => self.sign_extend(packet),
+            (false, false) => packet as i16,
+        };
     }
 
     fn process_y_movement(&mut self, packet: u8) {
-        if !self.current_state.flags.contains(MouseFlags::Y_OVERFLOW) {
-            self.current_state.y = if self.current_state.flags.contains(MouseFlags::Y_SIGN) {
-                self.sign_extend(packet)
-            } else {
-                packet as i16
-            };
+        let flags = self.current_state.flags;
+        self.current_state.y = match (
+            flags.contains(MouseFlags::Y_OVERFLOW),
+            flags.contains(MouseFlags::Y_SIGN),
+        ) {
+            (true, true) => -256,
+            (true, false) => 255,
+            (false, true) => self.sign_extend(packet),
+            (false, false) => packet as i16,
+        };
+    }
+
+    fn process_extension(&mut self, packet: u8) {
+        match self.kind {
+            MouseKind::Wheel => self.current_state.z = packet as i8,
+            _ => {
+                //4 bit wheel movement and the two extra buttons
+                self.current_state.z = ((packet << 4) as i8) >> 4;
+                self.current_state.button_4 = packet & 0x10 != 0;
+                self.current_state.button_5 = packet & 0x20 != 0;
+            }
         }
     }
 
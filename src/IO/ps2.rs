@@ -0,0 +1,339 @@
+use crate::sync::IrqSafeMutex;
+use bitflags::bitflags;
+use x86_64::instructions::port::Port;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+const COMMAND_PORT_ADDRESS: u16 = 0x64;
+const DATA_PORT_ADDRESS: u16 = 0x60;
+
+//status register bits
+const OUTPUT_FULL: u8 = 0x01;
+const INPUT_FULL: u8 = 0x02;
+//the output byte came from the mouse
+const AUX_DATA: u8 = 0x20;
+
+//controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND_PORT: u8 = 0xA7;
+const ENABLE_SECOND_PORT: u8 = 0xA8;
+const TEST_SECOND_PORT: u8 = 0xA9;
+const WRITE_SECOND_PORT: u8 = 0xD4;
+//pulses the cpu reset line
+const PULSE_RESET: u8 = 0xFE;
+//translates scancode set 2 from the keyboard into set 1
+const CONFIG_TRANSLATION: u8 = 0x40;
+const CONFIG_FIRST_IRQ: u8 = 0x01;
+const CONFIG_SECOND_IRQ: u8 = 0x02;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 0x20;
+
+//keyboard commands
+const SET_LEDS: u8 = 0xED;
+const SCANCODE_SET: u8 = 0xF0;
+const SET_TYPEMATIC: u8 = 0xF3;
+const RESET: u8 = 0xFF;
+
+//device responses
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+const SELF_TEST_PASSED: u8 = 0xAA;
+
+//status polls before giving up, a port access takes about a microsecond so this is in the order of 100ms
+const TIMEOUT: usize = 100_000;
+const MAX_RESENDS: usize = 3;
+//the self test after a reset can take up to a second, far longer than one read timeout
+const SELF_TEST_READ_ATTEMPTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// the controller or device didn't respond in time
+    Timeout,
+    /// the device still asked for a resend after `MAX_RESENDS` attempts
+    Resend,
+    /// the device answered with something other than ACK or resend
+    UnexpectedResponse(u8),
+    /// the device answered its reset with this instead of self test passed
+    SelfTestFailed(u8),
+    /// the controller's test of a port answered with this instead of 0
+    PortTestFailed(u8),
+    /// no device was found on the port at initialization
+    NoDevice,
+}
+
+/// The devices behind the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Keyboard,
+    Mouse,
+}
+
+/// The 8042 PS/2 controller. Interrupts stay disabled while it is locked, so a command's responses can't be
+/// taken by the keyboard interrupt handler.
+pub struct Controller {
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+pub static CONTROLLER: IrqSafeMutex<Controller> =
+    IrqSafeMutex::new("io::ps2::CONTROLLER", Controller::new());
+
+impl Controller {
+    const fn new() -> Self {
+        Self {
+            command: Port::new(COMMAND_PORT_ADDRESS),
+            data: Port::new(DATA_PORT_ADDRESS),
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        unsafe { self.command.read() }
+    }
+
+    /// The waiting byte, if there is one
+    pub fn read_output(&mut self) -> Option<u8> {
+        self.read_output_from().map(|(_, byte)| byte)
+    }
+
+    /// The waiting byte and the device that sent it
+    pub fn read_output_from(&mut self) -> Option<(Device, u8)> {
+        let status = self.status();
+        if status & OUTPUT_FULL == 0 {
+            return None;
+        }
+        let device = if status & AUX_DATA != 0 {
+            Device::Mouse
+        } else {
+            Device::Keyboard
+        };
+        Some((device, unsafe { self.data.read() }))
+    }
+
+    /// Waits for the next byte from the controller or a device
+    pub fn read(&mut self) -> Result<u8, Ps2Error> {
+        for _ in 0..TIMEOUT {
+            if let Some(byte) = self.read_output() {
+                return Ok(byte);
+            }
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn wait_input_empty(&mut self) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT {
+            if self.status() & INPUT_FULL == 0 {
+                return Ok(());
+            }
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    pub fn write_command(&mut self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_input_empty()?;
+        unsafe { self.command.write(command) };
+        Ok(())
+    }
+
+    pub fn write_data(&mut self, data: u8) -> Result<(), Ps2Error> {
+        self.wait_input_empty()?;
+        unsafe { self.data.write(data) };
+        Ok(())
+    }
+
+    pub fn config(&mut self) -> Result<u8, Ps2Error> {
+        self.write_command(READ_CONFIG)?;
+        self.read()
+    }
+
+    pub fn set_config(&mut self, config: u8) -> Result<(), Ps2Error> {
+        self.write_command(WRITE_CONFIG)?;
+        self.write_data(config)
+    }
+
+    /// Tests the mouse port and turns it on with its interrupt still masked, so the mouse can be set up by polling
+    pub fn enable_second_port(&mut self) -> Result<(), Ps2Error> {
+        self.write_command(TEST_SECOND_PORT)?;
+        match self.read()? {
+            0 => {}
+            response => return Err(Ps2Error::PortTestFailed(response)),
+        }
+        self.write_command(ENABLE_SECOND_PORT)?;
+        let config = self.config()?;
+        self.set_config(config & !(CONFIG_SECOND_IRQ | CONFIG_SECOND_CLOCK_DISABLED))
+    }
+
+    pub fn disable_second_port(&mut self) -> Result<(), Ps2Error> {
+        self.write_command(DISABLE_SECOND_PORT)?;
+        let config = self.config()?;
+        self.set_config((config & !CONFIG_SECOND_IRQ) | CONFIG_SECOND_CLOCK_DISABLED)
+    }
+
+    /// Lets the mouse raise IRQ 12
+    pub fn enable_second_port_interrupt(&mut self) -> Result<(), Ps2Error> {
+        let config = self.config()?;
+        self.set_config(config | CONFIG_SECOND_IRQ)
+    }
+
+    /// Sends one byte to `device` and waits for its ACK, resending it when asked to.
+    /// Input from the other device arriving before the ACK is passed on, see `read_from`.
+    pub fn send(&mut self, device: Device, byte: u8) -> Result<(), Ps2Error> {
+        for _ in 0..MAX_RESENDS {
+            if device == Device::Mouse {
+                self.write_command(WRITE_SECOND_PORT)?;
+            }
+            self.write_data(byte)?;
+            match self.read_response(device)? {
+                ACK => return Ok(()),
+                RESEND => continue,
+                response => return Err(Ps2Error::UnexpectedResponse(response)),
+            }
+        }
+        Err(Ps2Error::Resend)
+    }
+
+    /// Waits for the next byte from `device`. Bytes from the other device are passed on as their interrupt handlers would.
+    pub fn read_from(&mut self, device: Device) -> Result<u8, Ps2Error> {
+        for _ in 0..TIMEOUT {
+            match self.read_output_from() {
+                Some((from, byte)) if from == device => return Ok(byte),
+                Some((Device::Keyboard, byte)) => crate::io::add_scancode(byte),
+                Some((Device::Mouse, byte)) => crate::io::MOUSE.lock().process_packet(byte),
+                None => {}
+            }
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn read_response(&mut self, device: Device) -> Result<u8, Ps2Error> {
+        loop {
+            match self.read_from(device)? {
+                //keys typed while waiting for the keyboard's answer
+                byte if device == Device::Keyboard && byte < ACK => crate::io::add_scancode(byte),
+                //packets the mouse sent before it got the command
+                byte if device == Device::Mouse && byte != ACK && byte != RESEND => {
+                    crate::io::MOUSE.lock().process_packet(byte)
+                }
+                byte => return Ok(byte),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+/// How long a key has to be held before it repeats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeatDelay {
+    Ms250 = 0,
+    Ms500 = 1,
+    Ms750 = 2,
+    Ms1000 = 3,
+}
+
+bitflags! {
+    /// The lock LEDs on the keyboard
+    #[derive(Default)]
+    pub struct Leds: u8 {
+        const SCROLL_LOCK = 0b0000_0001;
+        const NUM_LOCK = 0b0000_0010;
+        const CAPS_LOCK = 0b0000_0100;
+    }
+}
+
+/// Resets the keyboard and waits for its self test
+pub fn reset_keyboard() -> Result<(), Ps2Error> {
+    reset_device(Device::Keyboard)
+}
+
+/// Resets `device` and waits for its self test. That can take up to a second, so the controller is only locked for
+/// one read at a time. The port interrupts are off meanwhile, their handlers would take the result otherwise.
+pub fn reset_device(device: Device) -> Result<(), Ps2Error> {
+    let config = {
+        let mut controller = CONTROLLER.lock();
+        let config = controller.config()?;
+        controller.set_config(config & !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ))?;
+        config
+    };
+    let result = CONTROLLER
+        .lock()
+        .send(device, RESET)
+        .and_then(|_| read_self_test(device));
+    CONTROLLER.lock().set_config(config)?;
+    result
+}
+
+fn read_self_test(device: Device) -> Result<(), Ps2Error> {
+    let mut response = Err(Ps2Error::Timeout);
+    for _ in 0..SELF_TEST_READ_ATTEMPTS {
+        response = CONTROLLER.lock().read_from(device);
+        if response != Err(Ps2Error::Timeout) {
+            break;
+        }
+    }
+    match response? {
+        SELF_TEST_PASSED => Ok(()),
+        response => Err(Ps2Error::SelfTestFailed(response)),
+    }
+}
+
+/// Selects the scancode set the keyboard sends. The controller translates set 2 into set 1 and is told to leave
+/// set 1 alone, so `ScancodeDecoder` gets set 1 either way.
+pub fn set_scancode_set(set: ScancodeSet) -> Result<(), Ps2Error> {
+    let mut controller = CONTROLLER.lock();
+    let (number, translation) = match set {
+        ScancodeSet::Set1 => (1, false),
+        ScancodeSet::Set2 => (2, true),
+    };
+    controller.send(Device::Keyboard, SCANCODE_SET)?;
+    controller.send(Device::Keyboard, number)?;
+    let config = controller.config()?;
+    if translation {
+        controller.set_config(config | CONFIG_TRANSLATION)
+    } else {
+        controller.set_config(config & !CONFIG_TRANSLATION)
+    }
+}
+
+/// `rate` goes from 0 (30 repeats per second) to 31 (2 per second)
+pub fn set_typematic(delay: RepeatDelay, rate: u8) -> Result<(), Ps2Error> {
+    let mut controller = CONTROLLER.lock();
+    controller.send(Device::Keyboard, SET_TYPEMATIC)?;
+    controller.send(Device::Keyboard, ((delay as u8) << 5) | rate.min(31))
+}
+
+pub fn set_leds(leds: Leds) -> Result<(), Ps2Error> {
+    let mut controller = CONTROLLER.lock();
+    controller.send(Device::Keyboard, SET_LEDS)?;
+    controller.send(Device::Keyboard, leds.bits())
+}
+
+/// Puts the keyboard into a known state: reset, scancode set 2 translated to set 1, 500ms delay at about
+/// 10 repeats per second and all LEDs off
+pub fn init_keyboard() -> Result<(), Ps2Error> {
+    reset_keyboard()?;
+    set_scancode_set(ScancodeSet::Set2)?;
+    set_typematic(RepeatDelay::Ms500, 0x0B)?;
+    set_leds(Leds::empty())
+}
+
+/// Resets the machine through the controller's reset line. If that doesn't work, an empty IDT turns the next
+/// interrupt into a triple fault.
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+    let _ = CONTROLLER.lock().write_command(PULSE_RESET);
+    for _ in 0..TIMEOUT {
+        core::hint::spin_loop();
+    }
+    unsafe {
+        x86_64::instructions::tables::lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+        x86_64::instructions::interrupts::int3();
+    }
+    crate::hlt_loop()
+}
@@ -1,7 +1,20 @@
+use crate::interrupts::{InterruptIndex, PICS};
+use crate::sync::IrqSafeMutex;
+use crate::warn;
+#[cfg(feature = "capture")]
+use alloc::string::String;
 use bitflags::bitflags;
+use conquer_once::spin::OnceCell;
 use core::fmt::{Result, Write};
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
 
 bitflags! {
@@ -22,6 +35,113 @@ macro_rules! wait_for {
     };
 }
 
+/// The standard serial ports of a PC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl ComPort {
+    pub const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    pub fn base(self) -> u16 {
+        match self {
+            ComPort::Com1 => 0x3F8,
+            ComPort::Com2 => 0x2F8,
+            ComPort::Com3 => 0x3E8,
+            ComPort::Com4 => 0x2E8,
+        }
+    }
+
+    /// Whether `init_serial` found a working UART at this port
+    pub fn is_present(self) -> bool {
+        PRESENT_PORTS.load(Ordering::Relaxed) & (1 << self as u8) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordLength {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// parity bit always 1
+    Mark,
+    /// parity bit always 0
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    /// 1.5 with 5 bit words
+    Two,
+}
+
+/// Line settings of a serial port, the default is 38400/8-N-1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// Bits per second, should divide 115200 evenly
+    pub baud: u32,
+    pub word_length: WordLength,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl SerialConfig {
+    pub const DEFAULT: SerialConfig = SerialConfig {
+        baud: 38400,
+        word_length: WordLength::Eight,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+    };
+
+    //the UART clock divided by 16
+    const MAX_BAUD: u32 = 115200;
+
+    pub fn divisor(&self) -> u16 {
+        (Self::MAX_BAUD / self.baud.max(1)).clamp(1, u32::from(u16::MAX)) as u16
+    }
+
+    /// Value of the line control register
+    pub fn line_ctrl(&self) -> u8 {
+        let word_length = match self.word_length {
+            WordLength::Five => 0b00,
+            WordLength::Six => 0b01,
+            WordLength::Seven => 0b10,
+            WordLength::Eight => 0b11,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => 1 << 2,
+        };
+        let parity = match self.parity {
+            Parity::None => 0b000,
+            Parity::Odd => 0b001,
+            Parity::Even => 0b011,
+            Parity::Mark => 0b101,
+            Parity::Space => 0b111,
+        } << 3;
+        word_length | stop_bits | parity
+    }
+}
+
 pub struct SerialPort {
     data: Port<u8>,
     int_en: PortWriteOnly<u8>,
@@ -29,6 +149,8 @@ pub struct SerialPort {
     line_ctrl: PortWriteOnly<u8>,
     modem_ctrl: PortWriteOnly<u8>,
     line_sts: PortReadOnly<u8>,
+    config: SerialConfig,
+    receive_interrupt: bool,
 }
 
 impl SerialPort {
@@ -41,11 +163,21 @@ impl SerialPort {
             line_ctrl: PortWriteOnly::new(base + 3),
             modem_ctrl: PortWriteOnly::new(base + 4),
             line_sts: PortReadOnly::new(base + 5),
+            config: SerialConfig::DEFAULT,
+            receive_interrupt: false,
         }
     }
 
-    /// The default configuration of 38400/8-N-1 is used
+    /// The default configuration of 38400/8-N-1 is used and the receive interrupt is enabled
     pub fn init(&mut self) {
+        self.receive_interrupt = true;
+        self.configure(SerialConfig::default());
+    }
+
+    /// Changes the line settings, anything still in the FIFOs is thrown away
+    pub fn configure(&mut self, config: SerialConfig) {
+        self.config = config;
+        let [divisor_low, divisor_high] = config.divisor().to_le_bytes();
         unsafe {
             // Disable interrupts
             self.int_en.write(0x00);
@@ -53,12 +185,12 @@ impl SerialPort {
             // Enable DLAB
             self.line_ctrl.write(0x80);
 
-            // Set maximum speed to 38400 bps by configuring DLL and DLM
-            self.data.write(0x03);
-            self.int_en.write(0x00);
+            // Set the speed by configuring DLL and DLM
+            self.data.write(divisor_low);
+            self.int_en.write(divisor_high);
 
-            // Disable DLAB and set data word length to 8 bits
-            self.line_ctrl.write(0x03);
+            // Disable DLAB and set word length, parity and stop bits
+            self.line_ctrl.write(config.line_ctrl());
 
             // Enable FIFO, clear TX/RX queues and
             // set interrupt watermark at 14 bytes
@@ -68,33 +200,70 @@ impl SerialPort {
             // and enable auxilliary output #2 (used as interrupt line for CPU)
             self.modem_ctrl.write(0x0B);
 
-            // Enable interrupts
-            self.int_en.write(0x01);
+            // Enable the received data interrupt
+            if self.receive_interrupt {
+                self.int_en.write(0x01);
+            }
+        }
+    }
+
+    pub fn config(&self) -> SerialConfig {
+        self.config
+    }
+
+    /// Checks that there is a working UART by sending a byte to itself in loopback mode.
+    ///
+    /// Reconfigures the port afterwards, so anything that was in its FIFOs is lost.
+    pub fn self_test(&mut self) -> bool {
+        const TEST_BYTE: u8 = 0xAE;
+        unsafe {
+            self.int_en.write(0x00);
+            self.fifo_ctrl.write(0xC7);
         }
+        self.set_loopback(true);
+        unsafe { self.data.write(TEST_BYTE) };
+        //nothing there reads as 0xFF, which looks like a full receive buffer holding 0xFF
+        let received = (0..1000).find_map(|_| self.receive());
+        self.set_loopback(false);
+        self.configure(self.config);
+        received == Some(TEST_BYTE)
     }
 
     fn line_sts(&mut self) -> LineStsFlags {
         unsafe { LineStsFlags::from_bits_truncate(self.line_sts.read()) }
     }
 
+    /// Returns the next received byte, None if nothing arrived
+    pub fn receive(&mut self) -> Option<u8> {
+        if self.line_sts().contains(LineStsFlags::INPUT_FULL) {
+            Some(unsafe { self.data.read() })
+        } else {
+            None
+        }
+    }
+
+    /// In loopback mode everything sent is received by the port itself instead of going out on the line
+    pub fn set_loopback(&mut self, enabled: bool) {
+        let loopback = if enabled { 0x10 } else { 0x00 };
+        unsafe { self.modem_ctrl.write(0x0B | loopback) }
+    }
+
     pub fn send(&mut self, data: u8) {
-        unsafe {
-            match data {
-                8 | 0x7F => {
-                    wait_for!(self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY));
-                    self.data.write(8);
-                    wait_for!(self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY));
-                    self.data.write(b' ');
-                    wait_for!(self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY));
-                    self.data.write(8)
-                }
-                _ => {
-                    wait_for!(self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY));
-                    self.data.write(data);
-                }
+        match data {
+            8 | 0x7F => {
+                self.send_raw(8);
+                self.send_raw(b' ');
+                self.send_raw(8)
             }
+            _ => self.send_raw(data),
         }
     }
+
+    /// Sends `data` as it is, `send` turns backspace and delete into erasing the last character on a terminal
+    pub fn send_raw(&mut self, data: u8) {
+        wait_for!(self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY));
+        unsafe { self.data.write(data) }
+    }
 }
 
 impl Write for SerialPort {
@@ -107,24 +276,278 @@ impl Write for SerialPort {
 }
 
 lazy_static! {
-    pub static ref SERIAL: Mutex<SerialPort> = {
+    /// COM1, kernel output goes here
+    pub static ref SERIAL: IrqSafeMutex<SerialPort> = {
         let mut serial_port = unsafe { SerialPort::new(0x3F8) };
         serial_port.init();
-        Mutex::new(serial_port)
+        IrqSafeMutex::new("io::SERIAL", serial_port)
     };
 }
 
+/// COM2 for debug protocols that must not be mixed with kernel output. None if there is no COM2.
+pub static DEBUG_SERIAL: IrqSafeMutex<Option<SerialPort>> =
+    IrqSafeMutex::new("io::DEBUG_SERIAL", None);
+
+//bit n is set if ComPort n passed the self test
+static PRESENT_PORTS: AtomicU8 = AtomicU8::new(0);
+
+/// Returns the UART at `port` configured with `config`, None if the port failed the self test.
+///
+/// COM1 is already owned by `SERIAL`, reconfigure that one instead.
+pub fn open_port(port: ComPort, config: SerialConfig) -> Option<SerialPort> {
+    //every ComPort is a standard serial port address, probing it is harmless
+    let mut serial_port = unsafe { SerialPort::new(port.base()) };
+    serial_port.config = config;
+    if serial_port.self_test() {
+        Some(serial_port)
+    } else {
+        None
+    }
+}
+
+//When set, everything written to the serial port is also appended here so tests can check the output
+#[cfg(feature = "capture")]
+static CAPTURE: IrqSafeMutex<Option<String>> = IrqSafeMutex::new("io::CAPTURE", None);
+
+/// Starts recording serial output, dropping anything recorded before
+#[cfg(feature = "capture")]
+pub fn start_capture() {
+    *CAPTURE.lock() = Some(String::new());
+}
+
+/// Stops recording and returns everything written since `start_capture`
+#[cfg(feature = "capture")]
+pub fn stop_capture() -> String {
+    CAPTURE.lock().take().unwrap_or_default()
+}
+
+//set while a SerialReservation exists or its held back text is flushed, only checked with SERIAL locked
+static RESERVED: AtomicBool = AtomicBool::new(false);
+//bytes sent per lock of SERIAL, about 4ms at 38400 baud
+const RESERVED_CHUNK_SIZE: usize = 16;
+const HELD_BACK_SIZE: usize = 4096;
+
+//text printed during a reservation, only touched with SERIAL locked
+static HELD_BACK: IrqSafeMutex<HeldBack> = IrqSafeMutex::new(
+    "io::serial::HELD_BACK",
+    HeldBack {
+        bytes: [0; HELD_BACK_SIZE],
+        start: 0,
+        len: 0,
+        dropped: 0,
+    },
+);
+
+//fixed size, so printing while COM1 is reserved doesn't allocate. Whatever doesn't fit is counted and dropped.
+struct HeldBack {
+    bytes: [u8; HELD_BACK_SIZE],
+    start: usize,
+    len: usize,
+    dropped: usize,
+}
+
+impl HeldBack {
+    fn push(&mut self, bytes: &[u8]) {
+        let end = self.start + self.len;
+        let fits = bytes.len().min(HELD_BACK_SIZE - end);
+        self.bytes[end..end + fits].copy_from_slice(&bytes[..fits]);
+        self.len += fits;
+        self.dropped += bytes.len() - fits;
+    }
+
+    //sends up to `max` bytes, returns false once there was nothing left
+    fn send(&mut self, serial: &mut SerialPort, max: usize) -> bool {
+        if self.len == 0 {
+            if self.dropped > 0 {
+                let _ = writeln!(serial, "[{} bytes of output dropped]", self.dropped);
+            }
+            self.start = 0;
+            self.dropped = 0;
+            return false;
+        }
+        let count = self.len.min(max);
+        for byte in &self.bytes[self.start..self.start + count] {
+            serial.send_raw(*byte);
+        }
+        self.start += count;
+        self.len -= count;
+        true
+    }
+}
+
+impl Write for HeldBack {
+    fn write_str(&mut self, s: &str) -> Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Exclusive use of COM1 for binary data that must not get text mixed into it. While it exists, everything else
+/// printed is held back and sent once it is dropped.
+pub struct SerialReservation(());
+
+impl SerialReservation {
+    /// None while somebody else has it
+    pub fn acquire() -> Option<Self> {
+        //with the port locked, so no print that started before goes out after
+        let _serial = SERIAL.lock();
+        RESERVED
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| Self(()))
+    }
+
+    /// Sends `bytes` as they are. The port is only locked for a few bytes at a time, so interrupts aren't held
+    /// off for long.
+    pub fn send(&self, bytes: &[u8]) {
+        for chunk in bytes.chunks(RESERVED_CHUNK_SIZE) {
+            let mut serial = SERIAL.lock();
+            for byte in chunk {
+                serial.send_raw(*byte);
+            }
+        }
+    }
+
+    /// Ends the current reservation right away and sends what was held back, without waiting for the port. For panic
+    /// handlers - whatever the reservation was sending is cut off.
+    pub fn cancel() {
+        if let Some(mut serial) = SERIAL.try_lock() {
+            if let Some(mut held_back) = HELD_BACK.try_lock() {
+                while held_back.send(&mut serial, HELD_BACK_SIZE) {}
+            }
+        }
+        RESERVED.store(false, Ordering::Release);
+    }
+}
+
+//The held back text goes out a chunk at a time like the reserved data. Printing meanwhile is still held back,
+//so it comes after.
+impl Drop for SerialReservation {
+    fn drop(&mut self) {
+        loop {
+            let mut serial = SERIAL.lock();
+            if !HELD_BACK.lock().send(&mut serial, RESERVED_CHUNK_SIZE) {
+                RESERVED.store(false, Ordering::Release);
+                break;
+            }
+        }
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
-    use x86_64::instructions::interrupts;
-
-    //Make sure no interupts occur during lock to prevent deadlock
-    interrupts::without_interrupts(|| {
-        SERIAL
-            .lock()
+    //keep the port locked until the capture is written too, so both see the same order
+    let mut serial = SERIAL.lock();
+    if RESERVED.load(Ordering::Acquire) {
+        let _ = HELD_BACK.lock().write_fmt(args);
+    } else {
+        serial.write_fmt(args).expect("Printing to serial failed");
+    }
+    #[cfg(feature = "capture")]
+    if let Some(capture) = CAPTURE.lock().as_mut() {
+        capture
             .write_fmt(args)
-            .expect("Printing to serial failed");
-    });
+            .expect("Capturing serial output failed");
+    }
+}
+
+/// Sends raw bytes, e.g. output of user programs that doesn't have to be valid UTF-8
+pub fn write_bytes(bytes: &[u8]) {
+    let mut serial = SERIAL.lock();
+    if RESERVED.load(Ordering::Acquire) {
+        HELD_BACK.lock().push(bytes);
+    } else {
+        for byte in bytes {
+            serial.send_raw(*byte);
+        }
+    }
+    #[cfg(feature = "capture")]
+    if let Some(capture) = CAPTURE.lock().as_mut() {
+        capture.push_str(&String::from_utf8_lossy(bytes));
+    }
+}
+
+const INPUT_QUEUE_SIZE: usize = 256;
+
+//OnceCell for the same reason as the scancode queue: the interrupt handler must never be the one that allocates it
+static INPUT_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static INPUT_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Detects which serial ports exist, opens COM2 as `DEBUG_SERIAL`, sets up the input queue and lets the receive interrupt of COM1 through
+pub fn init_serial() {
+    INPUT_QUEUE
+        .try_init_once(|| ArrayQueue::new(INPUT_QUEUE_SIZE))
+        .expect("serial input queue already initialized");
+
+    let mut present = 0;
+    if SERIAL.lock().self_test() {
+        present |= 1 << ComPort::Com1 as u8;
+    }
+    for port in &ComPort::ALL[1..] {
+        if let Some(serial_port) = open_port(*port, SerialConfig::default()) {
+            present |= 1 << *port as u8;
+            if *port == ComPort::Com2 {
+                *DEBUG_SERIAL.lock() = Some(serial_port);
+            }
+        }
+    }
+    PRESENT_PORTS.store(present, Ordering::Relaxed);
+
+    unsafe { PICS.lock().unmask(InterruptIndex::Serial.as_u8()) };
+}
+
+/// Called by the serial interrupt handler - must not block or allocate.
+///
+/// Reads every byte the port has, the interrupt stays raised until the receive FIFO is empty.
+pub fn receive_serial() {
+    let mut dropped = false;
+    {
+        let mut serial = SERIAL.lock();
+        while let Some(byte) = serial.receive() {
+            match INPUT_QUEUE.try_get() {
+                Ok(queue) => dropped |= queue.push(byte).is_err(),
+                Err(_) => dropped = true,
+            }
+        }
+    }
+    INPUT_WAKER.wake();
+    //SERIAL has to be unlocked before warning about it over the same port
+    if dropped {
+        warn!("serial input queue full or uninitialized; dropping input");
+    }
+}
+
+/// Bytes received on COM1, in the order they arrived
+pub struct SerialStream {
+    _private: (), //field to prevent construction of the struct from outside of the module
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        Self { _private: () }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = INPUT_QUEUE
+            .try_get()
+            .expect("serial input queue not initialized");
+        if let Ok(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+        //register before checking again so a byte arriving in between isn't missed
+        INPUT_WAKER.register(cx.waker());
+        match queue.pop() {
+            Ok(byte) => {
+                INPUT_WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            Err(_) => Poll::Pending,
+        }
+    }
 }
 
 /// Prints to the host through the serial interface.
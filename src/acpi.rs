@@ -0,0 +1,106 @@
+use crate::memory;
+use alloc::vec::Vec;
+use core::ptr::read_unaligned;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+const SDT_HEADER_SIZE: u64 = 36;
+
+//MADT entry types
+const LOCAL_APIC: u8 = 0;
+const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// A processor from the MADT
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub acpi_id: u8,
+    pub apic_id: u8,
+    /// false if the firmware says the processor can't be started
+    pub usable: bool,
+}
+
+/// The parts of the Multiple APIC Description Table needed to start the other processors
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    pub processors: Vec<Processor>,
+}
+
+fn read<T: Copy>(phys: u64) -> T {
+    let virt = memory::physical_memory_offset() + phys;
+    unsafe { read_unaligned(virt.as_ptr::<T>()) }
+}
+
+fn checksum_ok(phys: u64, len: u64) -> bool {
+    (0..len).fold(0u8, |sum, i| sum.wrapping_add(read::<u8>(phys + i))) == 0
+}
+
+//The RSDP is in the first KiB of the EBDA or in the BIOS area below 1MiB, always 16 byte aligned
+fn find_rsdp() -> Option<u64> {
+    let ebda = u64::from(read::<u16>(0x40E)) << 4;
+    let areas = [(ebda, ebda + 1024), (0xE0000, 0x100000)];
+    areas
+        .iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .find(|&addr| read::<[u8; 8]>(addr) == *RSDP_SIGNATURE && checksum_ok(addr, 20))
+}
+
+/// Finds the MADT through the RSDT (ACPI 1.0) or the XSDT (ACPI 2.0+)
+pub fn find_madt() -> Option<Madt> {
+    let rsdp = find_rsdp()?;
+    let revision = read::<u8>(rsdp + 15);
+    let (root, entry_size) = if revision >= 2 {
+        (read::<u64>(rsdp + 24), 8)
+    } else {
+        (u64::from(read::<u32>(rsdp + 16)), 4)
+    };
+    if !checksum_ok(root, u64::from(read::<u32>(root + 4))) {
+        return None;
+    }
+
+    //a broken table may claim to be shorter than its own header
+    let entries = u64::from(read::<u32>(root + 4)).checked_sub(SDT_HEADER_SIZE)? / entry_size;
+    let madt = (0..entries)
+        .map(|i| root + SDT_HEADER_SIZE + i * entry_size)
+        .map(|entry| match entry_size {
+            8 => read::<u64>(entry),
+            _ => u64::from(read::<u32>(entry)),
+        })
+        .find(|&table| read::<[u8; 4]>(table) == *MADT_SIGNATURE)?;
+    let length = u64::from(read::<u32>(madt + 4));
+    if !checksum_ok(madt, length) {
+        return None;
+    }
+
+    let mut local_apic_address = u64::from(read::<u32>(madt + SDT_HEADER_SIZE));
+    let mut processors = Vec::new();
+    let mut entry = madt + SDT_HEADER_SIZE + 8;
+    while entry + 2 <= madt + length {
+        let (kind, entry_length) = (read::<u8>(entry), read::<u8>(entry + 1));
+        if entry_length < 2 {
+            break; //broken table, don't loop forever
+        }
+        match kind {
+            LOCAL_APIC => {
+                let flags = read::<u32>(entry + 4);
+                processors.push(Processor {
+                    acpi_id: read(entry + 2),
+                    apic_id: read(entry + 3),
+                    usable: flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0,
+                });
+            }
+            LOCAL_APIC_ADDRESS_OVERRIDE => local_apic_address = read(entry + 4),
+            _ => {}
+        }
+        entry += u64::from(entry_length);
+    }
+
+    Some(Madt {
+        local_apic_address,
+        processors,
+    })
+}
@@ -6,6 +6,7 @@ use x86_64::{
 };
 
 use self::linked_list::LinkedListAllocator;
+use crate::sync::{IrqSafeMutex, IrqSafeMutexGuard};
 
 pub mod linked_list;
 
@@ -42,18 +43,19 @@ pub fn init_heap(
     Ok(())
 }
 
+//the scheduler frees stacks of dead threads from inside interrupts, so the heap lock has to keep them disabled
 pub struct Locked<A> {
-    inner: spin::Mutex<A>,
+    inner: IrqSafeMutex<A>,
 }
 
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Self {
-            inner: spin::Mutex::new(inner),
+            inner: IrqSafeMutex::new("allocator::ALLOCATOR", inner),
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, A> {
         self.inner.lock()
     }
 }
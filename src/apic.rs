@@ -0,0 +1,125 @@
+use crate::memory;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::{
+    mapper::MapToError, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Where the local APIC registers get mapped, next to the kernel heap so every address space shares the mapping
+const LAPIC_VIRT: u64 = 0x_4444_8888_0000;
+
+//register offsets
+const ID: u64 = 0x20;
+const EOI: u64 = 0xB0;
+const SPURIOUS: u64 = 0xF0;
+const ICR_LOW: u64 = 0x300;
+const ICR_HIGH: u64 = 0x310;
+const LVT_TIMER: u64 = 0x320;
+const TIMER_INITIAL: u64 = 0x380;
+const TIMER_CURRENT: u64 = 0x390;
+const TIMER_DIVIDE: u64 = 0x3E0;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+const DELIVERY_INIT: u32 = 0b101 << 8;
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+const TIMER_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const DIVIDE_BY_16: u32 = 0b0011;
+
+/// Vector of the spurious interrupts the local APIC raises, they must not be acknowledged
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+//0 until init mapped the registers
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Maps the local APIC registers at physical address `phys`. They are at the same address on every cpu.
+pub fn init(phys: u64) -> Result<(), MapToError<Size4KiB>> {
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(LAPIC_VIRT));
+    let frame = PhysFrame::containing_address(PhysAddr::new(phys));
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    memory::with_frame_allocator(|frame_allocator| unsafe {
+        let mut mapper = memory::active_mapper();
+        match mapper.map_to(page, frame, flags, frame_allocator) {
+            Ok(flush) => flush.flush(),
+            Err(MapToError::PageAlreadyMapped(_)) => {} //init called twice
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    })?;
+    BASE.store(LAPIC_VIRT + (phys & 0xFFF), Ordering::Release);
+    Ok(())
+}
+
+pub fn is_initialized() -> bool {
+    BASE.load(Ordering::Acquire) != 0
+}
+
+fn read(register: u64) -> u32 {
+    let base = BASE.load(Ordering::Relaxed);
+    assert!(base != 0, "apic::init has not been called");
+    unsafe { ((base + register) as *const u32).read_volatile() }
+}
+
+fn write(register: u64, value: u32) {
+    let base = BASE.load(Ordering::Relaxed);
+    assert!(base != 0, "apic::init has not been called");
+    unsafe { ((base + register) as *mut u32).write_volatile(value) }
+}
+
+/// APIC id of the cpu that is running this
+pub fn id() -> u8 {
+    (read(ID) >> 24) as u8
+}
+
+/// Software enables the local APIC of the current cpu
+pub fn enable() {
+    write(SPURIOUS, SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR));
+}
+
+pub fn end_of_interrupt() {
+    write(EOI, 0);
+}
+
+fn send_ipi(apic_id: u8, command: u32) {
+    write(ICR_HIGH, u32::from(apic_id) << 24);
+    //writing the low half sends it
+    write(ICR_LOW, command);
+    while read(ICR_LOW) & DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Resets the cpu `apic_id` into its wait-for-startup state
+pub fn send_init(apic_id: u8) {
+    send_ipi(apic_id, DELIVERY_INIT | LEVEL_ASSERT);
+}
+
+/// Starts the cpu `apic_id` in real mode at `page * 4096`
+pub fn send_startup(apic_id: u8, page: u8) {
+    send_ipi(apic_id, DELIVERY_STARTUP | LEVEL_ASSERT | u32::from(page));
+}
+
+/// Lets the timer count down from the maximum without raising interrupts, for measuring its speed
+pub fn start_measuring_timer() {
+    write(TIMER_DIVIDE, DIVIDE_BY_16);
+    write(LVT_TIMER, TIMER_MASKED);
+    write(TIMER_INITIAL, u32::MAX);
+}
+
+/// How far the timer counted down since start_measuring_timer
+pub fn measured_timer_count() -> u32 {
+    u32::MAX - read(TIMER_CURRENT)
+}
+
+/// Raises `vector` on the current cpu every `count` timer ticks
+pub fn start_periodic_timer(vector: u8, count: u32) {
+    write(TIMER_DIVIDE, DIVIDE_BY_16);
+    write(LVT_TIMER, TIMER_PERIODIC | u32::from(vector));
+    write(TIMER_INITIAL, count);
+}
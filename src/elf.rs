@@ -0,0 +1,270 @@
+use crate::memory::AddressSpace;
+use crate::usermode::{self, USER_STACK_SIZE, USER_STACK_TOP};
+use alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/// Test program that prints its arguments, built from programs/hello.s
+pub static HELLO: &[u8] = include_bytes!("../programs/hello");
+/// Test program that yields forever, built from programs/spin.s
+pub static SPIN: &[u8] = include_bytes!("../programs/spin");
+
+const MAGIC: [u8; 4] = *b"\x7fELF";
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 62;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    Not64Bit,
+    NotLittleEndian,
+    BadVersion,
+    NotExecutable,
+    WrongMachine,
+    /// the program header table doesn't fit into the file
+    BadProgramHeaders,
+    /// a segment's file data doesn't fit into the file
+    SegmentOutOfBounds,
+    SegmentOutsideUserSpace,
+    /// a segment has more bytes in the file than in memory
+    BadSegmentSize,
+    EntryNotExecutable,
+    ArgumentsTooLarge,
+    OutOfMemory,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+}
+
+impl ProgramHeader {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            kind: read_u32(bytes, 0),
+            flags: read_u32(bytes, 4),
+            offset: read_u64(bytes, 8),
+            vaddr: read_u64(bytes, 16),
+            file_size: read_u64(bytes, 32),
+            mem_size: read_u64(bytes, 40),
+        }
+    }
+
+    fn page_flags(&self) -> PageTableFlags {
+        let mut flags = PageTableFlags::empty();
+        if self.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if self.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.vaddr && addr - self.vaddr < self.mem_size
+    }
+}
+
+/// A validated, statically linked x86_64 executable
+pub struct Elf<'a> {
+    data: &'a [u8],
+    entry: u64,
+    header_offset: usize,
+    header_size: usize,
+    header_count: usize,
+}
+
+impl<'a> Elf<'a> {
+    /// Checks the ELF header and all program headers, so loading can only fail when memory runs out
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if data[..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != CLASS_64 {
+            return Err(ElfError::Not64Bit);
+        }
+        if data[5] != LITTLE_ENDIAN {
+            return Err(ElfError::NotLittleEndian);
+        }
+        if data[6] != VERSION_CURRENT || read_u32(data, 20) != u32::from(VERSION_CURRENT) {
+            return Err(ElfError::BadVersion);
+        }
+        if read_u16(data, 16) != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+        if read_u16(data, 18) != MACHINE_X86_64 {
+            return Err(ElfError::WrongMachine);
+        }
+
+        let header_offset = read_u64(data, 32);
+        let header_size = usize::from(read_u16(data, 54));
+        let header_count = usize::from(read_u16(data, 56));
+        let table_end = (header_size as u64)
+            .checked_mul(header_count as u64)
+            .and_then(|size| size.checked_add(header_offset));
+        if header_size < PROGRAM_HEADER_SIZE
+            || table_end.map_or(true, |end| end > data.len() as u64)
+        {
+            return Err(ElfError::BadProgramHeaders);
+        }
+
+        let elf = Self {
+            data,
+            entry: read_u64(data, 24),
+            header_offset: header_offset as usize,
+            header_size,
+            header_count,
+        };
+        for header in elf.loadable_segments() {
+            let file_end = header.offset.checked_add(header.file_size);
+            if file_end.map_or(true, |end| end > data.len() as u64) {
+                return Err(ElfError::SegmentOutOfBounds);
+            }
+            if header.file_size > header.mem_size {
+                return Err(ElfError::BadSegmentSize);
+            }
+            if !usermode::is_user_range(header.vaddr, header.mem_size) {
+                return Err(ElfError::SegmentOutsideUserSpace);
+            }
+        }
+        let entry_executable = elf
+            .loadable_segments()
+            .any(|header| header.flags & PF_X != 0 && header.contains(elf.entry));
+        if !entry_executable {
+            return Err(ElfError::EntryNotExecutable);
+        }
+        Ok(elf)
+    }
+
+    pub fn entry(&self) -> VirtAddr {
+        VirtAddr::new(self.entry)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.header_count).map(move |i| {
+            let start = self.header_offset + i * self.header_size;
+            ProgramHeader::parse(&self.data[start..start + PROGRAM_HEADER_SIZE])
+        })
+    }
+
+    fn loadable_segments(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        self.program_headers()
+            .filter(|header| header.kind == PT_LOAD && header.mem_size > 0)
+    }
+
+    /// Maps every PT_LOAD segment into `space` with its file data copied in and the rest (the BSS) zeroed
+    pub fn load(&self, space: &mut AddressSpace) -> Result<(), ElfError> {
+        for header in self.loadable_segments() {
+            let start = VirtAddr::new(header.vaddr);
+            usermode::map_user_region_in(space, start, header.mem_size, header.page_flags())
+                .map_err(|_| ElfError::OutOfMemory)?;
+
+            let file_data = &self.data[header.offset as usize..][..header.file_size as usize];
+            usermode::write_in(space, start, file_data);
+            //fresh frames are zeroed already, but the page might be shared with the previous segment
+            usermode::zero_in(
+                space,
+                start + header.file_size,
+                header.mem_size - header.file_size,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A program that is ready to be started with `usermode::enter` once its address space is active
+pub struct LoadedProgram {
+    pub address_space: AddressSpace,
+    pub entry: VirtAddr,
+    pub stack_pointer: VirtAddr,
+}
+
+/// Loads `data` into a new address space.
+///
+/// The program gets the usual System V process stack: argc at rsp, followed by the argv pointers, an empty environment and an empty auxiliary vector.
+pub fn load_program(data: &[u8], args: &[&str]) -> Result<LoadedProgram, ElfError> {
+    let elf = Elf::parse(data)?;
+    let mut address_space = AddressSpace::new().ok_or(ElfError::OutOfMemory)?;
+    elf.load(&mut address_space)?;
+    let stack_pointer = setup_stack(&mut address_space, args)?;
+    Ok(LoadedProgram {
+        address_space,
+        entry: elf.entry(),
+        stack_pointer,
+    })
+}
+
+fn setup_stack(space: &mut AddressSpace, args: &[&str]) -> Result<VirtAddr, ElfError> {
+    //the strings go at the very top, the pointers to them below
+    let strings_size: u64 = args.iter().map(|arg| arg.len() as u64 + 1).sum();
+    //argc, argv, NULL, envp NULL, AT_NULL auxv entry
+    let words = args.len() as u64 + 5;
+    //leave most of the stack to the program
+    if strings_size + words * 8 > USER_STACK_SIZE / 2 {
+        return Err(ElfError::ArgumentsTooLarge);
+    }
+
+    let stack_bottom = USER_STACK_TOP - USER_STACK_SIZE;
+    usermode::map_user_region_in(
+        space,
+        VirtAddr::new(stack_bottom),
+        USER_STACK_SIZE,
+        PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    )
+    .map_err(|_| ElfError::OutOfMemory)?;
+
+    let mut string_addr = USER_STACK_TOP - strings_size;
+    //16 byte aligned at the entry point, as the ABI requires
+    let stack_pointer = (string_addr - words * 8) & !0xF;
+
+    let mut block: Vec<u64> = Vec::with_capacity(words as usize);
+    block.push(args.len() as u64);
+    for arg in args {
+        usermode::write_in(space, VirtAddr::new(string_addr), arg.as_bytes());
+        usermode::write_in(space, VirtAddr::new(string_addr + arg.len() as u64), &[0]);
+        block.push(string_addr);
+        string_addr += arg.len() as u64 + 1;
+    }
+    block.extend_from_slice(&[0, 0, 0, 0]);
+
+    let bytes: Vec<u8> = block.iter().flat_map(|word| word.to_le_bytes()).collect();
+    usermode::write_in(space, VirtAddr::new(stack_pointer), &bytes);
+    Ok(VirtAddr::new(stack_pointer))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
@@ -1,135 +0,0 @@
-use alloc::boxed::Box;
-use alloc::task::Wake;
-use alloc::{collections::BTreeMap, sync::Arc};
-use core::sync::atomic::{AtomicU64, Ordering};
-use core::task::{Context, Poll, Waker};
-use core::{future::Future, pin::Pin};
-use crossbeam_queue::ArrayQueue;
-use x86_64::instructions::interrupts::{self, enable_and_hlt};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
-
-impl TaskId {
-    fn new() -> Self {
-        //Make sure each ID is unique
-        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
-        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed)) //fetch_add atomically increases the value and returns the previous value in one atomic operation - this ensures each ID is returned once
-    }
-}
-
-pub struct Task {
-    id: TaskId,
-    future: Pin<Box<dyn Future<Output = ()>>>,
-}
-
-impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
-        Self {
-            id: TaskId::new(), // new
-            future: Box::pin(future),
-        }
-    }
-
-    fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
-    }
-}
-
-struct TaskWaker {
-    task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-}
-
-impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(Self {
-            task_id,
-            task_queue,
-        }))
-    }
-
-    fn wake_task(&self) {
-        //ArrayQueue type modifications only requires a shared reference
-        self.task_queue.push(self.task_id).expect("task_queue full");
-    }
-}
-
-impl Wake for TaskWaker {
-    fn wake(self: Arc<Self>) {
-        self.wake_task();
-    }
-
-    //optional but can lead to better performance b.c. it avoids unnecessary reference count modifications
-    fn wake_by_ref(self: &Arc<Self>) {
-        self.wake_task();
-    }
-}
-
-pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>, //fixed sized ArrayQueue b.c. interrupt handlers should not allocate on push to this queue
-    waker_cache: BTreeMap<TaskId, Waker>,
-}
-
-impl Executor {
-    pub fn new() -> Self {
-        Self {
-            tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
-        }
-    }
-
-    pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
-            panic!("task with same ID already in tasks");
-        }
-        self.task_queue.push(task_id).expect("queue full");
-    }
-
-    fn run_ready_tasks(&mut self) {
-        while let Ok(task_id) = self.task_queue.pop() {
-            let task = match self.tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // task no longer exists
-            };
-            let waker = self
-                .waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, self.task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
-                    self.tasks.remove(&task_id);
-                    self.waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
-            }
-        }
-    }
-
-    fn sleep_if_idle(&self) {
-        //Disable and re-enable interrupts to prevent race conditions
-        interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_and_hlt();
-        } else {
-            interrupts::enable();
-        }
-    }
-
-    pub fn run(&mut self) -> ! {
-        loop {
-            self.run_ready_tasks();
-            self.sleep_if_idle()
-        }
-    }
-
-    //Need access to run_ready_tasks to actually finish the test instead of looping endlessly
-    pub fn test_run(&mut self) {
-        self.run_ready_tasks()
-    }
-}
@@ -0,0 +1,576 @@
+use alloc::boxed::Box;
+use alloc::task::Wake;
+use alloc::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::{arch::x86_64::_rdtsc, fmt, future::Future, pin::Pin};
+use crossbeam_queue::ArrayQueue;
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts::{self, enable_and_hlt};
+
+use crate::serial_println;
+
+pub mod sync;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        //Make sure each ID is unique
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed)) //fetch_add atomically increases the value and returns the previous value in one atomic operation - this ensures each ID is returned once
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Counters the executor keeps for every task, used by `Executor::dump`
+#[derive(Default)]
+struct TaskStats {
+    polls: AtomicU64,
+    poll_cycles: AtomicU64, //total time spent in poll, in TSC cycles
+    last_wake_tick: AtomicUsize,
+}
+
+/// State shared between a running task and its `JoinHandle`.
+struct JoinState<T> {
+    output: Mutex<Option<T>>,
+    finished: AtomicBool,
+    aborted: AtomicBool,
+    task_waker: AtomicWaker, //wakes the task itself so an abort is noticed even if the task is idle
+    join_waker: AtomicWaker, //wakes whoever is awaiting the JoinHandle
+}
+
+impl<T> JoinState<T> {
+    fn new() -> Self {
+        Self {
+            output: Mutex::new(None),
+            finished: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+            task_waker: AtomicWaker::new(),
+            join_waker: AtomicWaker::new(),
+        }
+    }
+}
+
+/// Wraps the user future so its output ends up in the `JoinState` instead of being thrown away
+struct Joinable<T> {
+    future: Pin<Box<dyn Future<Output = T> + Send>>,
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> Future for Joinable<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.state.aborted.load(Ordering::Acquire) {
+            //returning Ready makes the executor drop the inner future
+            self.state.finished.store(true, Ordering::Release);
+            self.state.join_waker.wake();
+            return Poll::Ready(());
+        }
+        self.state.task_waker.register(cx.waker());
+
+        match self.future.as_mut().poll(cx) {
+            Poll::Ready(output) => {
+                *self.state.output.lock() = Some(output);
+                self.state.finished.store(true, Ordering::Release);
+                self.state.join_waker.wake();
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Scheduling class of a task. Every class gets polled each round, higher ones just get more polls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High = 0,
+    Normal = 1,
+    Low = 2,
+}
+
+impl Priority {
+    const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    //How many tasks of this priority may be polled in a single scheduling round
+    fn poll_budget(self) -> usize {
+        match self {
+            Priority::High => 8,
+            Priority::Normal => 4,
+            Priority::Low => 1,
+        }
+    }
+
+    fn as_usize(self) -> usize {
+        self as usize
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+pub struct Task<T = ()> {
+    id: TaskId,
+    name: Option<&'static str>,
+    priority: Priority,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    state: Arc<JoinState<T>>,
+}
+
+//Tasks have to be Send since they can be handed to the executor through the shared spawn queue
+impl<T: Send + 'static> Task<T> {
+    pub fn new(future: impl Future<Output = T> + Send + 'static) -> Self {
+        let state = Arc::new(JoinState::new());
+        Self {
+            id: TaskId::new(), // new
+            name: None,
+            priority: Priority::Normal,
+            future: Box::pin(Joinable {
+                future: Box::pin(future),
+                state: state.clone(),
+            }),
+            state,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Names the task in `Executor::dump` output
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    //Split the task into the type erased part the executor stores and the handle that is returned to the spawner
+    fn into_raw(self) -> (RawTask, JoinHandle<T>) {
+        let raw = RawTask {
+            id: self.id,
+            name: self.name,
+            priority: self.priority,
+            future: self.future,
+            queued: Arc::new(AtomicBool::new(false)),
+            stats: Arc::new(TaskStats::default()),
+        };
+        let handle = JoinHandle { state: self.state };
+        (raw, handle)
+    }
+}
+
+/// A task after its output type has been erased
+struct RawTask {
+    id: TaskId,
+    name: Option<&'static str>,
+    priority: Priority,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    queued: Arc<AtomicBool>, //set while the task id sits in the ready queue so repeated wakes don't push it twice
+    stats: Arc<TaskStats>,
+}
+
+impl RawTask {
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        let start = unsafe { _rdtsc() };
+        let result = self.future.as_mut().poll(context);
+        let cycles = unsafe { _rdtsc() }.wrapping_sub(start);
+
+        self.stats.polls.fetch_add(1, Ordering::Relaxed);
+        self.stats.poll_cycles.fetch_add(cycles, Ordering::Relaxed);
+        result
+    }
+}
+
+/// Returned when awaiting a `JoinHandle` whose task did not run to completion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    Aborted,
+}
+
+/// An owned permission to await or abort a spawned task.
+///
+/// Dropping the handle detaches the task - it keeps running but its output is discarded.
+pub struct JoinHandle<T> {
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Stops the task the next time the executor looks at it. Awaiting the handle afterwards returns `JoinError::Aborted`
+    pub fn abort(&self) {
+        if self.state.finished.load(Ordering::Acquire) {
+            return;
+        }
+        self.state.aborted.store(true, Ordering::Release);
+        self.state.task_waker.wake();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.finished.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, JoinError>> {
+        //register before checking to avoid missing a wakeup that happens in between
+        self.state.join_waker.register(cx.waker());
+        if !self.state.finished.load(Ordering::Acquire) {
+            return Poll::Pending;
+        }
+        match self.state.output.lock().take() {
+            Some(output) => Poll::Ready(Ok(output)),
+            None => Poll::Ready(Err(JoinError::Aborted)),
+        }
+    }
+}
+
+/// A cloneable handle that can spawn tasks onto an executor from anywhere, including from inside a running task.
+#[derive(Clone)]
+pub struct Spawner {
+    new_tasks: Arc<Mutex<VecDeque<RawTask>>>,
+}
+
+impl Spawner {
+    fn new() -> Self {
+        Self {
+            new_tasks: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn spawn<T: Send + 'static>(&self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_raw();
+        //the executor might be halted, so make sure it can't miss the new task between checking the queue and halting
+        interrupts::without_interrupts(|| self.new_tasks.lock().push_back(task));
+        handle
+    }
+
+    fn pop(&self) -> Option<RawTask> {
+        interrupts::without_interrupts(|| self.new_tasks.lock().pop_front())
+    }
+
+    fn is_empty(&self) -> bool {
+        interrupts::without_interrupts(|| self.new_tasks.lock().is_empty())
+    }
+}
+
+//Spawner of the most recently created executor
+static SPAWNER: Mutex<Option<Spawner>> = Mutex::new(None);
+
+/// Spawns a task onto the global executor. Panics if no executor was created yet.
+pub fn spawn<T: Send + 'static>(task: Task<T>) -> JoinHandle<T> {
+    try_spawn(task).expect("no executor to spawn the task on")
+}
+
+/// Like `spawn`, but returns None and drops the task if no executor was created yet
+pub fn try_spawn<T: Send + 'static>(task: Task<T>) -> Option<JoinHandle<T>> {
+    let spawner = SPAWNER.lock().clone()?;
+    Some(spawner.spawn(task))
+}
+
+/// Ids of the tasks that are ready to be polled, one queue per priority
+struct ReadyQueue {
+    queues: [ArrayQueue<TaskId>; 3], //fixed sized ArrayQueue b.c. interrupt handlers should not allocate on push to this queue
+    overflowed: AtomicBool,
+}
+
+impl ReadyQueue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            queues: [
+                ArrayQueue::new(capacity),
+                ArrayQueue::new(capacity),
+                ArrayQueue::new(capacity),
+            ],
+            overflowed: AtomicBool::new(false),
+        }
+    }
+
+    /// Called from interrupt handlers - must not block, allocate or panic.
+    fn push(&self, task_id: TaskId, priority: Priority) {
+        //A full queue is not fatal: the task stays marked as queued and the executor picks it up when it sweeps for overflowed tasks
+        if self.queues[priority.as_usize()].push(task_id).is_err() {
+            self.overflowed.store(true, Ordering::Release);
+        }
+    }
+
+    fn pop(&self, priority: Priority) -> Option<TaskId> {
+        self.queues[priority.as_usize()].pop().ok()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.iter().all(|queue| queue.is_empty()) && !self.overflowed.load(Ordering::Acquire)
+    }
+}
+
+/// Lets the executor run other ready tasks before the current one continues.
+pub async fn yield_now() {
+    struct YieldNow {
+        yielded: bool,
+    }
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            if self.yielded {
+                return Poll::Ready(());
+            }
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    YieldNow { yielded: false }.await
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    priority: Priority,
+    queued: Arc<AtomicBool>,
+    stats: Arc<TaskStats>,
+    ready_queue: Arc<ReadyQueue>,
+}
+
+impl TaskWaker {
+    fn new(task: &RawTask, ready_queue: Arc<ReadyQueue>) -> Waker {
+        Waker::from(Arc::new(Self {
+            task_id: task.id,
+            priority: task.priority,
+            queued: task.queued.clone(),
+            stats: task.stats.clone(),
+            ready_queue,
+        }))
+    }
+
+    fn wake_task(&self) {
+        self.stats
+            .last_wake_tick
+            .store(crate::timer::ticks(), Ordering::Relaxed);
+        //only the first wake after a poll has to queue the task
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.ready_queue.push(self.task_id, self.priority);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    //optional but can lead to better performance b.c. it avoids unnecessary reference count modifications
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+pub const DEFAULT_TASK_QUEUE_CAPACITY: usize = 100;
+
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks the running executor to print its task table. Safe to call from interrupt handlers, e.g. for a hotkey.
+pub fn request_dump() {
+    DUMP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, RawTask>,
+    ready_queue: Arc<ReadyQueue>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+    spawner: Spawner,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_TASK_QUEUE_CAPACITY)
+    }
+
+    /// Creates an executor whose ready queue holds `capacity` task ids.
+    ///
+    /// More tasks than that can exist, the queue only bounds how many can be woken without the executor sweeping for them.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let spawner = Spawner::new();
+        *SPAWNER.lock() = Some(spawner.clone());
+        Self {
+            tasks: BTreeMap::new(),
+            ready_queue: Arc::new(ReadyQueue::new(capacity)),
+            waker_cache: BTreeMap::new(),
+            spawner,
+        }
+    }
+
+    pub fn spawn<T: Send + 'static>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_raw();
+        self.insert_task(task);
+        handle
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        self.spawner.clone()
+    }
+
+    fn insert_task(&mut self, task: RawTask) {
+        let (task_id, priority) = (task.id, task.priority);
+        task.queued.store(true, Ordering::Release);
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.ready_queue.push(task_id, priority);
+    }
+
+    //Re-queue every task that was woken while the ready queue was full. The queue is rebuilt: what is still in it
+    //keeps its place and the dropped tasks go behind it, so no task ends up in it twice and fills it up again.
+    fn requeue_overflowed_tasks(&mut self) {
+        if !self.ready_queue.overflowed.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let mut in_queue = BTreeSet::new();
+        for priority in Priority::ALL {
+            let mut queued = Vec::new();
+            while let Some(task_id) = self.ready_queue.pop(priority) {
+                if in_queue.insert(task_id) {
+                    queued.push(task_id);
+                }
+            }
+            for task_id in queued {
+                self.ready_queue.push(task_id, priority);
+            }
+        }
+        for (task_id, task) in self.tasks.iter() {
+            if task.queued.load(Ordering::Acquire) && !in_queue.contains(task_id) {
+                self.ready_queue.push(*task_id, task.priority);
+            }
+        }
+    }
+
+    //Move tasks that were spawned through a Spawner into the executor
+    fn spawn_new_tasks(&mut self) {
+        while let Some(task) = self.spawner.pop() {
+            self.insert_task(task);
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        loop {
+            self.spawn_new_tasks();
+            self.requeue_overflowed_tasks();
+            if self.ready_queue.is_empty() {
+                break;
+            }
+            //One round - every priority gets its budget of polls so a task that keeps waking itself can't starve the others
+            for priority in Priority::ALL {
+                for _ in 0..priority.poll_budget() {
+                    match self.ready_queue.pop(priority) {
+                        Some(task_id) => self.poll_task(task_id),
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
+        let task = match self.tasks.get_mut(&task_id) {
+            Some(task) => task,
+            None => return, // task no longer exists
+        };
+        //clear before polling so a wake during the poll queues the task again
+        task.queued.store(false, Ordering::Release);
+        let waker = self
+            .waker_cache
+            .entry(task_id)
+            .or_insert_with(|| TaskWaker::new(task, self.ready_queue.clone()));
+        let mut context = Context::from_waker(waker);
+        match task.poll(&mut context) {
+            Poll::Ready(()) => {
+                // task done -> remove it and its cached waker
+                self.tasks.remove(&task_id);
+                self.waker_cache.remove(&task_id);
+            }
+            Poll::Pending => {}
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        //Disable and re-enable interrupts to prevent race conditions
+        interrupts::disable();
+        if self.ready_queue.is_empty() && self.spawner.is_empty() {
+            enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+
+    /// Prints a table of all tasks with their counters over serial
+    pub fn dump(&self) {
+        serial_println!(
+            "{:>5}  {:<16} {:<6} {:>3} {:>10} {:>16} {:>10}",
+            "id",
+            "name",
+            "prio",
+            "rdy",
+            "polls",
+            "cycles",
+            "last wake"
+        );
+        for task in self.tasks.values() {
+            serial_println!(
+                "{:>5}  {:<16} {:<6} {:>3} {:>10} {:>16} {:>10}",
+                task.id,
+                task.name.unwrap_or("-"),
+                task.priority.name(),
+                if task.queued.load(Ordering::Relaxed) {
+                    "*"
+                } else {
+                    ""
+                },
+                task.stats.polls.load(Ordering::Relaxed),
+                task.stats.poll_cycles.load(Ordering::Relaxed),
+                task.stats.last_wake_tick.load(Ordering::Relaxed)
+            );
+        }
+        serial_println!("{} tasks", self.tasks.len());
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            if DUMP_REQUESTED.swap(false, Ordering::Relaxed) {
+                self.dump();
+            }
+            self.run_ready_tasks();
+            self.sleep_if_idle()
+        }
+    }
+
+    //Need access to run_ready_tasks to actually finish the test instead of looping endlessly
+    pub fn test_run(&mut self) {
+        self.run_ready_tasks()
+    }
+}
@@ -0,0 +1,13 @@
+//! Async aware synchronization primitives for kernel tasks.
+//!
+//! Unlike `spin::Mutex` these park the waiting task with its waker instead of spinning, so the executor can run other tasks in the meantime.
+//! They are meant to be used between tasks only - interrupt handlers must keep using the lock free queues.
+
+pub mod mpsc;
+mod mutex;
+pub mod oneshot;
+mod semaphore;
+mod wait_queue;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
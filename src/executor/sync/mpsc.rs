@@ -0,0 +1,217 @@
+use super::wait_queue::WaitQueue;
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use futures_util::stream::Stream;
+use spin::Mutex;
+
+struct State<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver_alive: bool,
+    receiver_waker: Option<Waker>,
+    send_waiters: WaitQueue,
+}
+
+type Shared<T> = Arc<Mutex<State<T>>>;
+
+/// The receiver was dropped, the unsent value is handed back
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+/// Creates a bounded multi producer, single consumer channel.
+///
+/// Senders wait for free space once `capacity` values are buffered.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be at least 1");
+    let shared = Arc::new(Mutex::new(State {
+        buffer: VecDeque::with_capacity(capacity),
+        capacity,
+        senders: 1,
+        receiver_alive: true,
+        receiver_waker: None,
+        send_waiters: WaitQueue::new(),
+    }));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Shared<T>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            shared: &self.shared,
+            value: Some(value),
+            slot: None,
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.shared.lock();
+        if !state.receiver_alive {
+            return Err(TrySendError::Closed(value));
+        }
+        if state.buffer.len() >= state.capacity {
+            return Err(TrySendError::Full(value));
+        }
+        state.buffer.push_back(value);
+        let waker = state.receiver_waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            //wake the receiver so it sees the channel is closed
+            let waker = state.receiver_waker.take();
+            drop(state);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Future returned by `Sender::send`
+pub struct SendFuture<'a, T> {
+    shared: &'a Shared<T>,
+    value: Option<T>,
+    slot: Option<usize>,
+}
+
+//the value is only ever moved out as a whole, it is never pinned
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), SendError<T>>> {
+        let this = &mut *self;
+        let mut state = this.shared.lock();
+        let value = this.value.take().expect("SendFuture polled after completion");
+        if !state.receiver_alive {
+            state.send_waiters.finish(&mut this.slot);
+            return Poll::Ready(Err(SendError(value)));
+        }
+        if state.buffer.len() >= state.capacity {
+            state.send_waiters.register(&mut this.slot, cx.waker());
+            this.value = Some(value);
+            return Poll::Pending;
+        }
+        state.buffer.push_back(value);
+        state.send_waiters.finish(&mut this.slot);
+        let waker = state.receiver_waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Drop for SendFuture<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        //a cancelled sender that was already woken must hand the free slot to the next one
+        if state.send_waiters.cancel(&mut self.slot) {
+            if let Some(waker) = state.send_waiters.pop() {
+                drop(state);
+                waker.wake();
+            }
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Shared<T>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value. Returns `None` once all senders are dropped and the buffer is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        core::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        let mut state = self.shared.lock();
+        let value = state.buffer.pop_front();
+        let waker = value.as_ref().and_then(|_| state.send_waiters.pop());
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        value
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<T>> {
+        let mut state = self.shared.lock();
+        if let Some(value) = state.buffer.pop_front() {
+            //a slot just became free
+            let waker = state.send_waiters.pop();
+            drop(state);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+            return Poll::Ready(Some(value));
+        }
+        if state.senders == 0 {
+            return Poll::Ready(None);
+        }
+        //stored with the lock held so a send can't slip in before the waker is registered
+        state.receiver_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        let wakers = state.send_waiters.take_all();
+        drop(state);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+struct Inner<T> {
+    value: Mutex<Option<T>>,
+    complete: AtomicBool, //set once the sender sent a value or was dropped
+    receiver_dropped: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// The sender was dropped without sending a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+/// Creates a channel for sending a single value from one task to another
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        value: Mutex::new(None),
+        complete: AtomicBool::new(false),
+        receiver_dropped: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends the value to the receiver. Hands it back if the receiver is already gone.
+    pub fn send(self, value: T) -> Result<(), T> {
+        if self.inner.receiver_dropped.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        *self.inner.value.lock() = Some(value);
+        //dropping self marks the channel complete and wakes the receiver
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.receiver_dropped.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.complete.store(true, Ordering::Release);
+        self.inner.waker.wake();
+    }
+}
+
+/// Future that resolves to the sent value
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Returns the value if it was already sent without waiting for it
+    pub fn try_recv(&mut self) -> Option<Result<T, RecvError>> {
+        if !self.inner.complete.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(self.inner.value.lock().take().ok_or(RecvError))
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, RecvError>> {
+        //register before checking to avoid missing a send that happens in between
+        self.inner.waker.register(cx.waker());
+        match self.try_recv() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.receiver_dropped.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,121 @@
+use super::wait_queue::WaitQueue;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use spin::Mutex;
+
+struct SemaphoreState {
+    permits: usize,
+    waiters: WaitQueue,
+}
+
+/// Counting semaphore. Tasks waiting for a permit are woken in the order they started waiting.
+pub struct Semaphore {
+    state: Mutex<SemaphoreState>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(SemaphoreState {
+                permits,
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock();
+        if state.permits == 0 {
+            return None;
+        }
+        state.permits -= 1;
+        Some(SemaphorePermit { semaphore: self })
+    }
+
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            slot: None,
+        }
+    }
+
+    pub fn add_permits(&self, permits: usize) {
+        let mut state = self.state.lock();
+        state.permits += permits;
+        let waker = state.waiters.pop();
+        drop(state);
+        //only one waiter is woken, it passes the wakeup on if there are permits left after it took one
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by `Semaphore::acquire`
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    slot: Option<usize>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<SemaphorePermit<'a>> {
+        let semaphore = self.semaphore;
+        let mut state = semaphore.state.lock();
+        if state.permits == 0 {
+            state.waiters.register(&mut self.slot, cx.waker());
+            return Poll::Pending;
+        }
+        state.permits -= 1;
+        state.waiters.finish(&mut self.slot);
+        let next = if state.permits > 0 {
+            state.waiters.pop()
+        } else {
+            None
+        };
+        drop(state);
+        if let Some(waker) = next {
+            waker.wake();
+        }
+        Poll::Ready(SemaphorePermit { semaphore })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let mut state = self.semaphore.state.lock();
+        //a cancelled waiter that was already woken must hand the wakeup to the next one
+        if state.waiters.cancel(&mut self.slot) && state.permits > 0 {
+            if let Some(waker) = state.waiters.pop() {
+                drop(state);
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A permit from a `Semaphore`, given back when dropped
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl SemaphorePermit<'_> {
+    /// Drops the permit without giving it back to the semaphore
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(1);
+    }
+}
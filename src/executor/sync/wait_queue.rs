@@ -0,0 +1,63 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::task::Waker;
+
+/// FIFO of parked tasks.
+///
+/// Every waiting future keeps the id of its entry so polling it again only updates the stored waker instead of queueing the task twice.
+pub(super) struct WaitQueue {
+    next_id: usize,
+    waiters: VecDeque<(usize, Waker)>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            next_id: 0,
+            waiters: VecDeque::new(),
+        }
+    }
+
+    pub fn register(&mut self, slot: &mut Option<usize>, waker: &Waker) {
+        if let Some(id) = *slot {
+            if let Some((_, stored)) = self.waiters.iter_mut().find(|(other, _)| *other == id) {
+                if !stored.will_wake(waker) {
+                    *stored = waker.clone();
+                }
+                return;
+            }
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.waiters.push_back((id, waker.clone()));
+        *slot = Some(id);
+    }
+
+    /// Removes the entry of a waiter that got what it was waiting for
+    pub fn finish(&mut self, slot: &mut Option<usize>) {
+        if let Some(id) = slot.take() {
+            self.waiters.retain(|(other, _)| *other != id);
+        }
+    }
+
+    /// Removes the entry of a waiter that gave up. Returns true if it was already woken,
+    /// in which case the caller has to pass the wakeup on to the next waiter or it is lost.
+    pub fn cancel(&mut self, slot: &mut Option<usize>) -> bool {
+        match slot.take() {
+            Some(id) => {
+                let queued = self.waiters.iter().any(|(other, _)| *other == id);
+                self.waiters.retain(|(other, _)| *other != id);
+                !queued
+            }
+            None => false,
+        }
+    }
+
+    //Wakers are returned instead of woken so the caller can release its lock first
+    pub fn pop(&mut self) -> Option<Waker> {
+        self.waiters.pop_front().map(|(_, waker)| waker)
+    }
+
+    pub fn take_all(&mut self) -> Vec<Waker> {
+        self.waiters.drain(..).map(|(_, waker)| waker).collect()
+    }
+}
@@ -0,0 +1,567 @@
+use crate::interrupts::InterruptContext;
+use crate::io::{SerialPort, DEBUG_SERIAL};
+use crate::memory;
+use crate::sync::IrqSafeMutex;
+use alloc::boxed::Box;
+use x86_64::instructions::segmentation::{Segment, DS, ES, FS, GS};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::structures::paging::Translate;
+use x86_64::VirtAddr;
+
+/// Makes the cpu raise a debug exception after every instruction
+pub const TRAP_FLAG: u64 = 1 << 8;
+
+//same as what we announce in qSupported
+const PACKET_SIZE: usize = 4096;
+const MAX_BREAKPOINTS: usize = 32;
+const INT3: u8 = 0xCC;
+const SIGTRAP: u8 = 5;
+//rax..r15, rip, eflags, cs, ss, ds, es, fs, gs - the order gdb uses for amd64
+const REGISTER_COUNT: usize = 24;
+
+/// Byte stream to the debugger
+pub trait Connection: Send {
+    /// Waits for the next byte
+    fn read_byte(&mut self) -> u8;
+    fn write_byte(&mut self, byte: u8);
+}
+
+/// COM2 through `io::DEBUG_SERIAL`, locked for every byte so the port can be shared outside of debug sessions
+pub struct DebugSerial;
+
+impl Connection for DebugSerial {
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = DEBUG_SERIAL.lock().as_mut().and_then(SerialPort::receive) {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        if let Some(port) = DEBUG_SERIAL.lock().as_mut() {
+            port.send_raw(byte);
+        }
+    }
+}
+
+/// Why the debugger got control
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    /// int3
+    Breakpoint,
+    /// debug exception after a single step
+    Step,
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    original: u8,
+}
+
+//what to do once a packet is handled
+enum Action {
+    Reply,
+    Resume,
+    Detach,
+}
+
+//Packets are kept in fixed buffers: traps can happen with the heap locked, so a session must never allocate
+struct Stub {
+    connection: Box<dyn Connection>,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    packet: [u8; PACKET_SIZE],
+    reply: Reply,
+}
+
+struct Reply {
+    bytes: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.bytes[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        s.bytes().for_each(|byte| self.push(byte));
+    }
+
+    fn push_hex(&mut self, byte: u8) {
+        hex_digits(byte).iter().for_each(|digit| self.push(*digit));
+    }
+
+    //registers go over the wire in target byte order
+    fn push_register(&mut self, value: u64, size: usize) {
+        value.to_le_bytes()[..size]
+            .iter()
+            .for_each(|byte| self.push_hex(*byte));
+    }
+}
+
+static STUB: IrqSafeMutex<Option<Box<Stub>>> = IrqSafeMutex::new("gdb::STUB", None);
+
+/// Lets the debugger at the other end of `connection` take over on the next breakpoint or single step
+pub fn attach(connection: Box<dyn Connection>) {
+    let stub = Box::new(Stub {
+        connection,
+        breakpoints: [None; MAX_BREAKPOINTS],
+        packet: [0; PACKET_SIZE],
+        reply: Reply {
+            bytes: [0; PACKET_SIZE],
+            len: 0,
+        },
+    });
+    if let Some(mut old) = STUB.lock().replace(stub) {
+        old.remove_breakpoints();
+    }
+}
+
+/// Attaches the stub to COM2, returns false if there is none
+pub fn init() -> bool {
+    if DEBUG_SERIAL.lock().is_none() {
+        return false;
+    }
+    attach(Box::new(DebugSerial));
+    true
+}
+
+/// Removes every breakpoint and lets breakpoints be plain exceptions again
+pub fn detach() {
+    if let Some(mut stub) = STUB.lock().take() {
+        stub.remove_breakpoints();
+    }
+}
+
+pub fn is_attached() -> bool {
+    STUB.lock().is_some()
+}
+
+/// Stops here and hands control to the debugger, e.g. to set breakpoints right after booting
+pub fn breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
+/// Called by the breakpoint and debug exception handlers. Runs a debug session until the debugger continues
+/// or steps, all other cpus keep running meanwhile. Returns false if no debugger is attached.
+pub fn handle_trap(context: &mut InterruptContext, trap: Trap) -> bool {
+    let mut stub = STUB.lock();
+    let session = match stub.as_mut() {
+        Some(session) => session,
+        None => return false,
+    };
+    let mut software_breakpoint = false;
+    match trap {
+        //rip is behind the int3, go back to it so it looks like the breakpoint was hit before the instruction ran
+        Trap::Breakpoint if session.breakpoint_at(context.rip.wrapping_sub(1)).is_some() => {
+            context.rip -= 1;
+            software_breakpoint = true;
+        }
+        //another cpu's session removed the breakpoint while this one waited for the lock, just run the original instruction
+        Trap::Breakpoint
+            if is_mapped(context.rip.wrapping_sub(1), 1)
+                && unsafe { *((context.rip - 1) as *const u8) } != INT3 =>
+        {
+            context.rip -= 1;
+            return true;
+        }
+        Trap::Breakpoint => {}
+        Trap::Step => context.rflags &= !TRAP_FLAG,
+    }
+
+    session.reply_with_stop(software_breakpoint);
+    if let Action::Detach = session.serve(context) {
+        if let Some(mut session) = stub.take() {
+            session.remove_breakpoints();
+        }
+    }
+    true
+}
+
+impl Stub {
+    fn reply_with_stop(&mut self, software_breakpoint: bool) {
+        self.reply.len = 0;
+        self.reply.push(b'T');
+        self.reply.push_hex(SIGTRAP);
+        if software_breakpoint {
+            self.reply.push_str("swbreak:;");
+        }
+        self.send_reply();
+    }
+
+    //handles packets until the debugger lets the kernel run again
+    fn serve(&mut self, context: &mut InterruptContext) -> Action {
+        loop {
+            let len = self.receive_packet();
+            self.reply.len = 0;
+            match self.handle_packet(len, context) {
+                Action::Reply => self.send_reply(),
+                Action::Resume => return Action::Resume,
+                Action::Detach => {
+                    self.send_reply();
+                    return Action::Detach;
+                }
+            }
+        }
+    }
+
+    //waits for a packet with a valid checksum and returns the length of its data
+    fn receive_packet(&mut self) -> usize {
+        loop {
+            while self.connection.read_byte() != b'$' {}
+            let mut len = 0;
+            let mut sum: u8 = 0;
+            let mut overflow = false;
+            loop {
+                let byte = self.connection.read_byte();
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                if len < PACKET_SIZE {
+                    self.packet[len] = byte;
+                    len += 1;
+                } else {
+                    overflow = true;
+                }
+            }
+            let high = hex_digit(self.connection.read_byte());
+            let low = hex_digit(self.connection.read_byte());
+            if !overflow && high.zip(low).map(|(high, low)| high << 4 | low) == Some(sum) {
+                self.connection.write_byte(b'+');
+                return len;
+            }
+            self.connection.write_byte(b'-');
+        }
+    }
+
+    //sends the reply until the debugger acknowledges it
+    fn send_reply(&mut self) {
+        let data = &self.reply.bytes[..self.reply.len];
+        let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        loop {
+            self.connection.write_byte(b'$');
+            data.iter()
+                .for_each(|byte| self.connection.write_byte(*byte));
+            self.connection.write_byte(b'#');
+            hex_digits(sum)
+                .iter()
+                .for_each(|digit| self.connection.write_byte(*digit));
+            match self.connection.read_byte() {
+                b'-' => continue,
+                _ => return,
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, len: usize, context: &mut InterruptContext) -> Action {
+        let packet = &self.packet[..len];
+        let (command, args) = match packet.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Action::Reply,
+        };
+        let reply = &mut self.reply;
+        match command {
+            b'?' => {
+                reply.push(b'S');
+                reply.push_hex(SIGTRAP);
+            }
+            b'g' => {
+                for register in 0..REGISTER_COUNT {
+                    reply.push_register(read_register(context, register), register_size(register));
+                }
+            }
+            b'G' => {
+                let mut values = args.chunks(2).map(|pair| hex_byte(pair));
+                for register in 0..REGISTER_COUNT {
+                    let mut bytes = [0u8; 8];
+                    for byte in &mut bytes[..register_size(register)] {
+                        match values.next() {
+                            Some(Some(value)) => *byte = value,
+                            _ => return Action::Reply, //the debugger sent fewer registers, keep the rest
+                        }
+                    }
+                    write_register(context, register, u64::from_le_bytes(bytes));
+                }
+                reply.push_str("OK");
+            }
+            b'p' => match parse_hex(args).filter(|n| (*n as usize) < REGISTER_COUNT) {
+                Some(register) => {
+                    let register = register as usize;
+                    reply.push_register(read_register(context, register), register_size(register));
+                }
+                None => reply.push_str("E01"),
+            },
+            b'P' => {
+                let mut parts = args.splitn(2, |byte| *byte == b'=');
+                let register = parts.next().and_then(parse_hex).map(|n| n as usize);
+                let value = parts.next().and_then(parse_le_hex);
+                match (register, value) {
+                    (Some(register), Some(value)) if register < REGISTER_COUNT => {
+                        write_register(context, register, value);
+                        reply.push_str("OK");
+                    }
+                    _ => reply.push_str("E01"),
+                }
+            }
+            b'm' => match parse_address_length(args) {
+                Some((address, length)) if length <= (PACKET_SIZE / 2) as u64 => {
+                    if is_mapped(address, length) {
+                        for offset in 0..length {
+                            let byte = unsafe { *((address + offset) as *const u8) };
+                            reply.push_hex(byte);
+                        }
+                    } else {
+                        reply.push_str("E14");
+                    }
+                }
+                _ => reply.push_str("E01"),
+            },
+            b'M' => {
+                let mut parts = args.splitn(2, |byte| *byte == b':');
+                let target = parts.next().and_then(parse_address_length);
+                let data = parts.next().unwrap_or(&[]);
+                match target {
+                    Some((address, length)) if data.len() as u64 == length * 2 => {
+                        if !is_mapped(address, length) {
+                            reply.push_str("E14");
+                        } else if data.chunks(2).all(|pair| hex_byte(pair).is_some()) {
+                            for (offset, pair) in data.chunks(2).enumerate() {
+                                write_byte(address + offset as u64, hex_byte(pair).unwrap());
+                            }
+                            reply.push_str("OK");
+                        } else {
+                            reply.push_str("E01");
+                        }
+                    }
+                    _ => reply.push_str("E01"),
+                }
+            }
+            b'c' | b's' => {
+                if let Some(address) = parse_hex(args) {
+                    context.rip = address;
+                }
+                if command == b's' {
+                    context.rflags |= TRAP_FLAG;
+                } else {
+                    context.rflags &= !TRAP_FLAG;
+                }
+                return Action::Resume;
+            }
+            b'Z' | b'z' if args.starts_with(b"0,") => {
+                let address = parse_address_length(&args[2..]).map(|(address, _)| address);
+                let done = match address {
+                    Some(address) if command == b'Z' => self.insert_breakpoint(address),
+                    Some(address) => self.remove_breakpoint(address),
+                    None => false,
+                };
+                self.reply.push_str(if done { "OK" } else { "E01" });
+            }
+            //the kernel can't be killed, just let it run
+            b'k' => return Action::Resume,
+            b'D' => {
+                reply.push_str("OK");
+                return Action::Detach;
+            }
+            b'q' if args.starts_with(b"Supported") => {
+                reply.push_str("PacketSize=1000;swbreak+");
+            }
+            b'q' if args == b"Attached" => reply.push(b'1'),
+            //an empty reply tells gdb the packet isn't supported
+            _ => {}
+        }
+        Action::Reply
+    }
+
+    fn breakpoint_at(&self, address: u64) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| breakpoint.map(|b| b.address) == Some(address))
+    }
+
+    fn insert_breakpoint(&mut self, address: u64) -> bool {
+        if self.breakpoint_at(address).is_some() {
+            return true;
+        }
+        let slot = match self.breakpoints.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        if !is_mapped(address, 1) {
+            return false;
+        }
+        let original = unsafe { *(address as *const u8) };
+        write_byte(address, INT3);
+        self.breakpoints[slot] = Some(Breakpoint { address, original });
+        true
+    }
+
+    fn remove_breakpoint(&mut self, address: u64) -> bool {
+        match self.breakpoint_at(address) {
+            Some(slot) => {
+                let breakpoint = self.breakpoints[slot].take().unwrap();
+                write_byte(breakpoint.address, breakpoint.original);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_breakpoints(&mut self) {
+        for breakpoint in self.breakpoints.iter_mut().filter_map(Option::take) {
+            write_byte(breakpoint.address, breakpoint.original);
+        }
+    }
+}
+
+fn register_size(register: usize) -> usize {
+    if register <= 16 {
+        8
+    } else {
+        4
+    }
+}
+
+fn read_register(context: &InterruptContext, register: usize) -> u64 {
+    match register {
+        0 => context.rax,
+        1 => context.rbx,
+        2 => context.rcx,
+        3 => context.rdx,
+        4 => context.rsi,
+        5 => context.rdi,
+        6 => context.rbp,
+        7 => context.rsp,
+        8 => context.r8,
+        9 => context.r9,
+        10 => context.r10,
+        11 => context.r11,
+        12 => context.r12,
+        13 => context.r13,
+        14 => context.r14,
+        15 => context.r15,
+        16 => context.rip,
+        17 => context.rflags,
+        18 => context.cs,
+        19 => context.ss,
+        //the entry doesn't save these, they are the same for all kernel code
+        20 => u64::from(DS::get_reg().0),
+        21 => u64::from(ES::get_reg().0),
+        22 => u64::from(FS::get_reg().0),
+        23 => u64::from(GS::get_reg().0),
+        _ => 0,
+    }
+}
+
+//segment registers are ignored, iretq faults if they don't match the privilege level of the code
+fn write_register(context: &mut InterruptContext, register: usize, value: u64) {
+    let slot = match register {
+        0 => &mut context.rax,
+        1 => &mut context.rbx,
+        2 => &mut context.rcx,
+        3 => &mut context.rdx,
+        4 => &mut context.rsi,
+        5 => &mut context.rdi,
+        6 => &mut context.rbp,
+        7 => &mut context.rsp,
+        8 => &mut context.r8,
+        9 => &mut context.r9,
+        10 => &mut context.r10,
+        11 => &mut context.r11,
+        12 => &mut context.r12,
+        13 => &mut context.r13,
+        14 => &mut context.r14,
+        15 => &mut context.r15,
+        16 => &mut context.rip,
+        17 => {
+            context.rflags = (context.rflags & !0xFFFF_FFFF) | (value & 0xFFFF_FFFF);
+            return;
+        }
+        _ => return,
+    };
+    *slot = value;
+}
+
+//whether every byte in the range is mapped in the active page table, so reading it can't fault
+fn is_mapped(address: u64, length: u64) -> bool {
+    if length == 0 {
+        return true;
+    }
+    let last = match address.checked_add(length - 1) {
+        Some(last) => last,
+        None => return false,
+    };
+    //only reads the page table, so it doesn't need the frame allocator lock the debugged code might be holding
+    let mapper = unsafe { memory::active_mapper() };
+    (address / 4096..=last / 4096).all(|page| {
+        VirtAddr::try_new(page * 4096)
+            .ok()
+            .and_then(|page| mapper.translate_addr(page))
+            .is_some()
+    })
+}
+
+//kernel code is mapped read only, so write protection is off while the debugger changes it
+fn write_byte(address: u64, byte: u8) {
+    unsafe {
+        let cr0 = Cr0::read();
+        Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+        *(address as *mut u8) = byte;
+        Cr0::write(cr0);
+    }
+}
+
+fn hex_digits(byte: u8) -> [u8; 2] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    [
+        DIGITS[usize::from(byte >> 4)],
+        DIGITS[usize::from(byte & 0xF)],
+    ]
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|digit| digit as u8)
+}
+
+fn hex_byte(pair: &[u8]) -> Option<u8> {
+    match pair {
+        [high, low] => Some(hex_digit(*high)? << 4 | hex_digit(*low)?),
+        _ => None,
+    }
+}
+
+//big endian number like addresses and lengths
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, digit| {
+        Some(value << 4 | u64::from(hex_digit(*digit)?))
+    })
+}
+
+//register value in target byte order
+fn parse_le_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 || digits.len() % 2 != 0 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+        *byte = hex_byte(pair)?;
+    }
+    Some(u64::from_le_bytes(bytes))
+}
+
+//"addr,length"
+fn parse_address_length(args: &[u8]) -> Option<(u64, u64)> {
+    let mut parts = args.splitn(2, |byte| *byte == b',');
+    let address = parse_hex(parts.next()?)?;
+    let length = parse_hex(parts.next()?)?;
+    Some((address, length))
+}
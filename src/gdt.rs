@@ -1,12 +1,28 @@
+use crate::smp::MAX_CPUS;
+use alloc::{boxed::Box, vec};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const AP_STACK_SIZE: usize = 4096 * 5;
+
+//The TSS has to stay writable after it is loaded b.c. the kernel stack for ring 3 -> ring 0 switches changes with every thread
+struct TssCell(UnsafeCell<TaskStateSegment>);
+
+//every cpu only writes its own TSS, with interrupts disabled
+unsafe impl Sync for TssCell {}
+
+//The bootstrap cpu uses the statics below since it sets up its GDT before there is a heap, the others get leaked allocations
+const NO_TSS: AtomicPtr<TssCell> = AtomicPtr::new(ptr::null_mut());
+static CPU_TSS: [AtomicPtr<TssCell>; MAX_CPUS] = [NO_TSS; MAX_CPUS];
 
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
+    static ref TSS: TssCell = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
             const STACK_SIZE: usize = 4096 * 5;
@@ -16,37 +32,93 @@ lazy_static! {
             
             stack_start + STACK_SIZE
         };
-        tss
+        //used when ring 3 code is interrupted before any thread set its own kernel stack
+        tss.privilege_stack_table[0] = {
+            const STACK_SIZE: usize = 4096 * 5;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+
+            stack_start + STACK_SIZE
+        };
+        TssCell(UnsafeCell::new(tss))
     };
 }
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-            },
-        )
-    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = build_gdt(&TSS);
+}
+
+//every cpu gets the same layout, so selectors are the same everywhere
+fn build_gdt(tss: &'static TssCell) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*tss.0.get() }));
+    //user segments get a requested privilege level of 3 from add_entry
+    let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            tss_selector,
+            user_code_selector,
+            user_data_selector,
+        },
+    )
 }
 
 struct Selectors {
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
 }
 
+/// Loads the GDT and TSS of the bootstrap cpu
 pub fn init() {
+    CPU_TSS[0].store(&*TSS as *const TssCell as *mut TssCell, Ordering::Release);
+    load(&GDT);
+}
+
+/// Gives the application processor `cpu` its own GDT and TSS and loads them
+pub fn init_ap(cpu: usize) {
+    let stack_top = || {
+        let stack = Box::leak(vec![0u8; AP_STACK_SIZE].into_boxed_slice());
+        VirtAddr::from_ptr(stack.as_ptr()) + AP_STACK_SIZE
+    };
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_top();
+    tss.privilege_stack_table[0] = stack_top();
+    let tss: &'static TssCell = Box::leak(Box::new(TssCell(UnsafeCell::new(tss))));
+
+    CPU_TSS[cpu].store(tss as *const TssCell as *mut TssCell, Ordering::Release);
+    load(Box::leak(Box::new(build_gdt(tss))));
+}
+
+fn load(gdt: &'static (GlobalDescriptorTable, Selectors)) {
     use x86_64::instructions::segmentation::{Segment, CS};
     use x86_64::instructions::tables::load_tss;
 
-    GDT.0.load();
+    gdt.0.load();
+    unsafe {
+        CS::set_reg(gdt.1.code_selector);
+        load_tss(gdt.1.tss_selector);
+    }
+}
+
+/// Code and data selectors for ring 3
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector) {
+    (GDT.1.user_code_selector, GDT.1.user_data_selector)
+}
+
+/// Sets the stack `cpu` switches to when an interrupt or syscall arrives while running in ring 3.
+///
+/// Must be called on that cpu with interrupts disabled.
+pub fn set_kernel_stack(cpu: usize, stack_top: VirtAddr) {
+    let tss = CPU_TSS[cpu].load(Ordering::Acquire);
+    assert!(!tss.is_null(), "no TSS for cpu {}", cpu);
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+        (*(*tss).0.get()).privilege_stack_table[0] = stack_top;
     }
 }
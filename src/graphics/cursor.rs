@@ -0,0 +1,229 @@
+use crate::graphics::colors::Color16;
+use crate::graphics::vga::{HEIGHT, WIDTH};
+use crate::io::MOUSE;
+
+//the largest cursor image, in both directions
+const MAX_SIZE: usize = 16;
+
+const B: u8 = Color16::Black as u8;
+const W: u8 = Color16::White as u8;
+//masked out, the value doesn't matter
+const T: u8 = 0;
+
+/// A cursor bitmap. `pixels` has a color for each pixel, row by row. `mask` has a row of bits for each row, starting
+/// with the leftmost pixel at bit 0: pixels with their bit set are drawn, the others let the frame show through.
+#[derive(Debug, Clone, Copy)]
+pub struct CursorImage {
+    width: usize,
+    height: usize,
+    hotspot: (usize, usize),
+    pixels: &'static [u8],
+    mask: &'static [u16],
+}
+
+impl CursorImage {
+    /// A white arrow with a black outline, pointing up and left
+    pub const ARROW: CursorImage = CursorImage::new(10, 16, (0, 0), &ARROW_PIXELS, &ARROW_MASK);
+
+    /// `width` and `height` are at most 16 and `hotspot` is the pixel that points at the cursor position.
+    /// Panics if the image doesn't fit that or `pixels` and `mask` are too short for it.
+    pub const fn new(
+        width: usize,
+        height: usize,
+        hotspot: (usize, usize),
+        pixels: &'static [u8],
+        mask: &'static [u16],
+    ) -> Self {
+        assert!(width <= MAX_SIZE && height <= MAX_SIZE);
+        assert!(hotspot.0 < width && hotspot.1 < height);
+        assert!(pixels.len() >= width * height && mask.len() >= height);
+        Self {
+            width,
+            height,
+            hotspot,
+            pixels,
+            mask,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn hotspot(&self) -> (usize, usize) {
+        self.hotspot
+    }
+
+    /// The color at `x`, `y` in the image, None where it is transparent
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        match self.mask[y] & (1 << x) {
+            0 => None,
+            _ => Some(self.pixels[y * self.width + x]),
+        }
+    }
+}
+
+//bit 0 is the leftmost pixel, so the rows read mirrored
+#[rustfmt::skip]
+const ARROW_MASK: [u16; 16] = [
+    0b0000000001,
+    0b0000000011,
+    0b0000000111,
+    0b0000001111,
+    0b0000011111,
+    0b0000111111,
+    0b0001111111,
+    0b0011111111,
+    0b0111111111,
+    0b1111111111,
+    0b1111111111,
+    0b0001111111,
+    0b0011110111,
+    0b0011110011,
+    0b0111100001,
+    0b0011100000,
+];
+
+#[rustfmt::skip]
+const ARROW_PIXELS: [u8; 10 * 16] = [
+    B, T, T, T, T, T, T, T, T, T,
+    B, B, T, T, T, T, T, T, T, T,
+    B, W, B, T, T, T, T, T, T, T,
+    B, W, W, B, T, T, T, T, T, T,
+    B, W, W, W, B, T, T, T, T, T,
+    B, W, W, W, W, B, T, T, T, T,
+    B, W, W, W, W, W, B, T, T, T,
+    B, W, W, W, W, W, W, B, T, T,
+    B, W, W, W, W, W, W, W, B, T,
+    B, W, W, W, W, W, W, W, W, B,
+    B, W, W, W, W, W, B, B, B, B,
+    B, W, W, B, W, W, B, T, T, T,
+    B, W, B, T, B, W, W, B, T, T,
+    B, B, T, T, B, W, W, B, T, T,
+    B, T, T, T, T, B, W, W, B, T,
+    T, T, T, T, T, B, B, B, T, T,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorMode {
+    Hidden,
+    /// Drawn into the back buffer right before `Vga::swap_buffers` copies it, the pixels underneath are put back
+    /// afterwards
+    Composited,
+    /// Drawn straight onto the screen after the copy, the back buffer is never touched. `Vga::update_cursor` moves
+    /// it without a new frame.
+    Overlay,
+}
+
+/// The mouse cursor of the graphics layer. It follows the mouse driver's position whenever it is drawn.
+pub(super) struct Cursor {
+    image: CursorImage,
+    mode: CursorMode,
+    position: (usize, usize),
+    //the top left corner the image was last drawn at and what was underneath it
+    drawn_at: Option<(isize, isize)>,
+    background: [u8; MAX_SIZE * MAX_SIZE],
+}
+
+impl Cursor {
+    pub(super) const fn new() -> Self {
+        Self {
+            image: CursorImage::ARROW,
+            mode: CursorMode::Composited,
+            position: (WIDTH / 2, HEIGHT / 2),
+            drawn_at: None,
+            background: [0; MAX_SIZE * MAX_SIZE],
+        }
+    }
+
+    pub(super) fn mode(&self) -> CursorMode {
+        self.mode
+    }
+
+    pub(super) fn set_mode(&mut self, mode: CursorMode) {
+        self.mode = mode;
+    }
+
+    pub(super) fn image(&self) -> CursorImage {
+        self.image
+    }
+
+    pub(super) fn set_image(&mut self, image: CursorImage) {
+        self.image = image;
+    }
+
+    pub(super) fn position(&self) -> (usize, usize) {
+        self.position
+    }
+
+    /// Without a mouse there is nothing to point with, so the cursor is only visible with one
+    pub(super) fn update_position(&mut self) -> bool {
+        let mouse = MOUSE.lock();
+        if mouse.is_present() {
+            self.position = mouse.position();
+        }
+        mouse.is_present()
+    }
+
+    /// Draws the image into `buffer` and remembers what it covered
+    pub(super) fn draw(&mut self, buffer: *mut u8) {
+        let left = self.position.0 as isize - self.image.hotspot.0 as isize;
+        let top = self.position.1 as isize - self.image.hotspot.1 as isize;
+        for y in 0..self.image.height {
+            for x in 0..self.image.width {
+                if let (Some(offset), Some(color)) = (
+                    offset(left + x as isize, top + y as isize),
+                    self.image.pixel(x, y),
+                ) {
+                    unsafe {
+                        self.background[y * MAX_SIZE + x] = buffer.add(offset).read_volatile();
+                        buffer.add(offset).write_volatile(color);
+                    }
+                }
+            }
+        }
+        self.drawn_at = Some((left, top));
+    }
+
+    /// Puts back what the last `draw` covered in `buffer`
+    pub(super) fn restore(&mut self, buffer: *mut u8) {
+        let (left, top) = match self.drawn_at.take() {
+            Some(corner) => corner,
+            None => return,
+        };
+        for y in 0..self.image.height {
+            for x in 0..self.image.width {
+                if let (Some(offset), Some(_)) = (
+                    offset(left + x as isize, top + y as isize),
+                    self.image.pixel(x, y),
+                ) {
+                    unsafe {
+                        buffer
+                            .add(offset)
+                            .write_volatile(self.background[y * MAX_SIZE + x]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forgets the last `draw`, for when its pixels were overwritten anyway
+    pub(super) fn discard(&mut self) {
+        self.drawn_at = None;
+    }
+}
+
+//offset of a pixel in a buffer, None if it is off screen
+fn offset(x: isize, y: isize) -> Option<usize> {
+    if x < 0 || y < 0 || x as usize >= WIDTH || y as usize >= HEIGHT {
+        return None;
+    }
+    Some(y as usize * WIDTH + x as usize)
+}
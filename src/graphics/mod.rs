@@ -1,7 +1,11 @@
 mod colors;
 mod configuration;
+mod cursor;
 mod lines;
 mod registers;
+mod screenshot;
 mod vga;
 
-pub use vga::VGA;
+pub use cursor::{CursorImage, CursorMode};
+pub use screenshot::send_screenshot;
+pub use vga::{Vga, VGA};
@@ -0,0 +1,46 @@
+use crate::executor::yield_now;
+use crate::graphics::colors::PALETTE_SIZE;
+use crate::graphics::vga::{HEIGHT, SIZE, WIDTH};
+use crate::graphics::VGA;
+use crate::io::SerialReservation;
+use crate::warn;
+use alloc::{format, vec::Vec};
+
+/// Sends the back buffer over COM1 as a binary PPM (P6) image between two marker lines, so it can be cut out of the serial log.
+/// The frame is copied first and sent one row at a time, so the other tasks keep running while it is sent. Nothing
+/// else is printed to COM1 until it is done.
+pub async fn send_screenshot() {
+    let serial = match SerialReservation::acquire() {
+        Some(serial) => serial,
+        None => {
+            warn!("COM1 is busy, not sending the screenshot");
+            return;
+        }
+    };
+    let mut palette = [0; PALETTE_SIZE];
+    let pixels = {
+        let mut vga = VGA.lock();
+        vga.color_palette_registers.read_palette(&mut palette);
+        let buffer = vga.get_buffer();
+        let mut pixels = Vec::with_capacity(SIZE);
+        pixels.extend((0..SIZE).map(|offset| unsafe { buffer.add(offset).read_volatile() }));
+        pixels
+    };
+
+    serial.send(format!("---- screenshot ----\nP6\n{} {}\n255\n", WIDTH, HEIGHT).as_bytes());
+    let mut rgb = Vec::with_capacity(WIDTH * 3);
+    for row in pixels.chunks(WIDTH) {
+        rgb.clear();
+        for &index in row {
+            //the palette has 6 bits per channel
+            rgb.extend(
+                palette[index as usize * 3..][..3]
+                    .iter()
+                    .map(|&channel| (channel << 2) | (channel >> 4)),
+            );
+        }
+        serial.send(&rgb);
+        yield_now().await;
+    }
+    serial.send(b"\n---- end of screenshot ----\n");
+}
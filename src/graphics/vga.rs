@@ -8,14 +8,15 @@ use super::{
 };
 use crate::graphics::{
     colors::DEFAULT_PALETTE,
+    cursor::{Cursor, CursorImage, CursorMode},
     lines::{Bresenham, Point},
 };
+use crate::sync::IrqSafeMutex;
 use alloc::{boxed::Box, string::String};
 use conquer_once::spin::Lazy;
 use core::ptr::copy_nonoverlapping;
 use font8x8::UnicodeFonts;
 use lazy_static::lazy_static;
-use spinning_top::Spinlock;
 
 /// The starting address for graphics modes.
 const FRAME_BUFFER: *mut u8 = 0xa0000 as *mut u8;
@@ -24,12 +25,13 @@ lazy_static! {
     static ref DOUBLE_BUFFER: Box<[u8; 320 * 200]> = Box::new([0; 320 * 200]);
 }
 
-const WIDTH: usize = 320;
-const HEIGHT: usize = 200;
-const SIZE: usize = WIDTH * HEIGHT;
+pub(super) const WIDTH: usize = 320;
+pub(super) const HEIGHT: usize = 200;
+pub(super) const SIZE: usize = WIDTH * HEIGHT;
 
 /// Provides mutable access to the vga graphics card.
-pub static VGA: Lazy<Spinlock<Vga>> = Lazy::new(|| Spinlock::new(Vga::new()));
+pub static VGA: Lazy<IrqSafeMutex<Vga>> =
+    Lazy::new(|| IrqSafeMutex::new("graphics::VGA", Vga::new()));
 
 /// Represents a vga graphics card with it's common registers.
 pub struct Vga {
@@ -45,6 +47,7 @@ pub struct Vga {
     pub crtc_controller_registers: CrtcControllerRegisters,
     /// Represents the color palette registers on vga hardware.
     pub color_palette_registers: ColorPaletteRegisters,
+    cursor: Cursor,
 }
 
 impl Vga {
@@ -56,6 +59,7 @@ impl Vga {
             attribute_controller_registers: AttributeControllerRegisters::new(),
             crtc_controller_registers: CrtcControllerRegisters::new(),
             color_palette_registers: ColorPaletteRegisters::new(),
+            cursor: Cursor::new(),
         }
     }
 
@@ -152,10 +156,68 @@ impl Vga {
             .unblank_screen(emulation_mode);
     }
 
-    pub fn swap_buffers(&self) {
+    /// Shows the back buffer, with the mouse cursor on top
+    pub fn swap_buffers(&mut self) {
+        let visible = self.cursor.update_position();
+        let mode = self.cursor.mode();
+        if visible && mode == CursorMode::Composited {
+            self.cursor.draw(self.get_buffer());
+        }
         unsafe {
             copy_nonoverlapping(self.get_buffer(), FRAME_BUFFER, SIZE);
         }
+        match mode {
+            CursorMode::Composited => self.cursor.restore(self.get_buffer()),
+            //the copy painted over the old overlay
+            CursorMode::Overlay => {
+                self.cursor.discard();
+                if visible {
+                    self.cursor.draw(FRAME_BUFFER);
+                }
+            }
+            CursorMode::Hidden => {}
+        }
+    }
+
+    /// Moves the overlay cursor to the mouse position without a new frame, for screens that aren't redrawn all the
+    /// time. The other modes only move with `swap_buffers`.
+    pub fn update_cursor(&mut self) {
+        if self.cursor.mode() != CursorMode::Overlay {
+            return;
+        }
+        self.cursor.restore(FRAME_BUFFER);
+        if self.cursor.update_position() {
+            self.cursor.draw(FRAME_BUFFER);
+        }
+    }
+
+    pub fn cursor_mode(&self) -> CursorMode {
+        self.cursor.mode()
+    }
+
+    /// Takes an overlay cursor off the screen when leaving the overlay mode, the others are gone with the next frame
+    pub fn set_cursor_mode(&mut self, mode: CursorMode) {
+        if self.cursor.mode() == CursorMode::Overlay {
+            self.cursor.restore(FRAME_BUFFER);
+        }
+        self.cursor.set_mode(mode);
+        self.update_cursor();
+    }
+
+    pub fn cursor_image(&self) -> CursorImage {
+        self.cursor.image()
+    }
+
+    pub fn set_cursor_image(&mut self, image: CursorImage) {
+        let mode = self.cursor.mode();
+        self.set_cursor_mode(CursorMode::Hidden);
+        self.cursor.set_image(image);
+        self.set_cursor_mode(mode);
+    }
+
+    /// Where the cursor was last drawn, in screen coordinates
+    pub fn cursor_position(&self) -> (usize, usize) {
+        self.cursor.position()
     }
 
     pub fn clear_screen(&self, color: u8) {
@@ -1,13 +1,14 @@
+use crate::gdb::{self, Trap};
 use crate::gdt;
 use crate::hlt_loop;
-use crate::io::MOUSE;
-use crate::serial_println;
+use crate::io::{Device, MOUSE};
+use crate::sync::IrqSafeMutex;
+use crate::{error, info, warn};
 use lazy_static::lazy_static;
-use spin;
 use x86_64::instructions::port::Port;
-use x86_64::instructions::port::PortReadOnly;
 use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::{PrivilegeLevel, VirtAddr};
 
 const CMD_INIT: u8 = 0x11;
 const CMD_END_OF_INTERRUPT: u8 = 0x20;
@@ -108,6 +109,18 @@ impl ChainedPics {
         self.pics[1].write_mask(mask2);
     }
 
+    /// Lets the IRQ that is mapped to `interrupt_id` through, in case the firmware masked it
+    pub unsafe fn unmask(&mut self, interrupt_id: u8) {
+        if let Some(pic) = self
+            .pics
+            .iter_mut()
+            .find(|p| p.handles_interrupt(interrupt_id))
+        {
+            let mask = pic.read_mask() & !(1 << (interrupt_id - pic.offset));
+            pic.write_mask(mask);
+        }
+    }
+
     fn handles_interrupt(&self, interrupt_id: u8) -> bool {
         self.pics.iter().any(|p| p.handles_interrupt(interrupt_id))
     }
@@ -125,19 +138,21 @@ impl ChainedPics {
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-pub static PICS: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PICS: IrqSafeMutex<ChainedPics> = IrqSafeMutex::new("interrupts::PICS", unsafe {
+    ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)
+});
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard, //Defaults to Timer + 1 (33)
+    Serial = PIC_1_OFFSET + 4,
     Mouse = PIC_1_OFFSET + 12,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
@@ -146,19 +161,168 @@ impl InterruptIndex {
     }
 }
 
+/// Software interrupt used by user programs to make syscalls
+pub const SYSCALL_VECTOR: u8 = 0x80;
+/// Software interrupt used by `thread::yield_now` to enter the scheduler
+pub const YIELD_VECTOR: u8 = 0x81;
+/// Local APIC timer of the application processors, the bootstrap cpu keeps using the PIT
+pub const LAPIC_TIMER_VECTOR: u8 = 0x30;
+
+/// All general purpose registers of the interrupted code followed by the frame the cpu pushed.
+///
+/// The layout has to match the push order of `push_registers!`.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct InterruptContext {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    // pushed by the cpu
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+macro_rules! push_registers {
+    () => {
+        "push rax
+        push rbx
+        push rcx
+        push rdx
+        push rsi
+        push rdi
+        push rbp
+        push r8
+        push r9
+        push r10
+        push r11
+        push r12
+        push r13
+        push r14
+        push r15"
+    };
+}
+
+macro_rules! pop_registers {
+    () => {
+        "pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rbp
+        pop rdi
+        pop rsi
+        pop rdx
+        pop rcx
+        pop rbx
+        pop rax"
+    };
+}
+
+/// Defines an interrupt entry point that saves every register as an `InterruptContext` and calls
+/// `extern "C" fn $handler(*mut InterruptContext) -> *mut InterruptContext`.
+///
+/// The handler returns the context to resume, which doesn't have to be the one that was interrupted - that's how threads are switched.
+/// Once the cpu runs on the new stack `thread::finish_switch` releases the old thread to the other cpus.
+macro_rules! context_switch_entry {
+    ($name:ident, $handler:path) => {
+        core::arch::global_asm!(
+            concat!(".global ", stringify!($name)),
+            concat!(stringify!($name), ":"),
+            push_registers!(),
+            // the cpu aligned the stack before pushing its 5 qword frame, so after 15 more pushes it's 16 byte aligned again
+            "mov rdi, rsp",
+            "call {handler}",
+            "mov rsp, rax",
+            "call {finish_switch}",
+            pop_registers!(),
+            "iretq",
+            handler = sym $handler,
+            finish_switch = sym crate::thread::finish_switch,
+        );
+
+        extern "C" {
+            fn $name();
+        }
+    };
+}
+
+/// Like `context_switch_entry!` for exceptions that never switch threads, the handler may only change the context in place.
+///
+/// Doesn't touch the scheduler, so these can be hit anywhere - e.g. a breakpoint inside the scheduler itself.
+macro_rules! trap_entry {
+    ($name:ident, $handler:path) => {
+        core::arch::global_asm!(
+            concat!(".global ", stringify!($name)),
+            concat!(stringify!($name), ":"),
+            push_registers!(),
+            "mov rdi, rsp",
+            "call {handler}",
+            pop_registers!(),
+            "iretq",
+            handler = sym $handler,
+        );
+
+        extern "C" {
+            fn $name();
+        }
+    };
+}
+
+context_switch_entry!(timer_interrupt_entry, timer_interrupt_handler);
+context_switch_entry!(yield_interrupt_entry, yield_interrupt_handler);
+context_switch_entry!(syscall_interrupt_entry, syscall_interrupt_handler);
+context_switch_entry!(lapic_timer_interrupt_entry, lapic_timer_interrupt_handler);
+trap_entry!(breakpoint_entry, breakpoint_handler);
+trap_entry!(debug_entry, debug_handler);
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+        unsafe {
+            idt.breakpoint
+                .set_handler_addr(VirtAddr::new(breakpoint_entry as *const () as u64));
+            idt.debug
+                .set_handler_addr(VirtAddr::new(debug_entry as *const () as u64));
+            idt[InterruptIndex::Timer.as_usize()]
+                .set_handler_addr(VirtAddr::new(timer_interrupt_entry as *const () as u64));
+            idt[usize::from(YIELD_VECTOR)]
+                .set_handler_addr(VirtAddr::new(yield_interrupt_entry as *const () as u64));
+            idt[usize::from(LAPIC_TIMER_VECTOR)]
+                .set_handler_addr(VirtAddr::new(lapic_timer_interrupt_entry as *const () as u64));
+            //the only gate ring 3 code may use
+            idt[usize::from(SYSCALL_VECTOR)]
+                .set_handler_addr(VirtAddr::new(syscall_interrupt_entry as *const () as u64))
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
         idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+        idt[InterruptIndex::Serial.as_usize()].set_handler_fn(serial_interrupt_handler);
+        idt[usize::from(crate::apic::SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -167,8 +331,21 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    serial_println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+//int3, either compiled in or placed by the debugger
+extern "C" fn breakpoint_handler(context: *mut InterruptContext) {
+    let context = unsafe { &mut *context };
+    if !gdb::handle_trap(context, Trap::Breakpoint) {
+        info!("EXCEPTION: BREAKPOINT\n{:#?}", context);
+    }
+}
+
+//raised after every instruction while the trap flag is set
+extern "C" fn debug_handler(context: *mut InterruptContext) {
+    let context = unsafe { &mut *context };
+    if !gdb::handle_trap(context, Trap::Step) {
+        context.rflags &= !gdb::TRAP_FLAG;
+        warn!("EXCEPTION: DEBUG at {:#x} without a debugger", context.rip);
+    }
 }
 
 #[test_case]
@@ -182,10 +359,12 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
-    serial_println!("EXCEPTION: PAGE FAULT");
-    serial_println!("Accessed Address: {:?}", Cr2::read());
-    serial_println!("Error Code: {:?}", error_code);
-    serial_println!("{:#?}", stack_frame);
+    error!(
+        "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+        Cr2::read(),
+        error_code,
+        stack_frame
+    );
     hlt_loop();
 }
 
@@ -196,20 +375,45 @@ extern "x86-interrupt" fn double_fault_handler(
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "C" fn timer_interrupt_handler(context: *mut InterruptContext) -> *mut InterruptContext {
     crate::timer::tick();
+    crate::io::keyboard_tick();
 
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+
+    //every tick ends the time slice of the running thread
+    crate::thread::schedule(context)
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
+extern "C" fn lapic_timer_interrupt_handler(
+    context: *mut InterruptContext,
+) -> *mut InterruptContext {
+    crate::smp::this_cpu()
+        .local_ticks
+        .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    crate::apic::end_of_interrupt();
+    crate::thread::schedule(context)
+}
+
+extern "C" fn yield_interrupt_handler(context: *mut InterruptContext) -> *mut InterruptContext {
+    crate::thread::schedule(context)
+}
+
+extern "C" fn syscall_interrupt_handler(context: *mut InterruptContext) -> *mut InterruptContext {
+    crate::syscall::dispatch(context)
+}
 
-    crate::io::add_scancode(scancode);
+//the local APIC doesn't expect an end of interrupt for these
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    //a command waiting for its ACK may already have taken the byte
+    if let Some(scancode) = crate::io::CONTROLLER.lock().read_output() {
+        crate::io::add_scancode(scancode);
+    }
 
     unsafe {
         PICS.lock()
@@ -218,12 +422,23 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 }
 
 extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    let mut port = PortReadOnly::new(0x60);
-    let packet: u8 = unsafe { port.read() };
-    MOUSE.lock().process_packet(packet);
+    match crate::io::CONTROLLER.lock().read_output_from() {
+        Some((Device::Mouse, packet)) => MOUSE.lock().process_packet(packet),
+        Some((Device::Keyboard, scancode)) => crate::io::add_scancode(scancode),
+        None => {}
+    }
 
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
     }
 }
+
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::io::receive_serial();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Serial.as_u8());
+    }
+}
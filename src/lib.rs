@@ -9,15 +9,26 @@
 
 extern crate alloc;
 
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
+pub mod elf;
 pub mod executor;
+pub mod gdb;
 pub mod gdt;
 pub mod graphics;
 pub mod interrupts;
 pub mod io;
+pub mod log;
 pub mod memory;
+pub mod process;
 pub mod render;
+pub mod smp;
+pub mod sync;
+pub mod syscall;
+pub mod thread;
 pub mod timer;
+pub mod usermode;
 
 use bootloader::BootInfo;
 use core::panic::PanicInfo;
@@ -55,6 +66,7 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    io::SerialReservation::cancel();
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failed);
@@ -104,6 +116,7 @@ fn panic(info: &PanicInfo) -> ! {
 
 pub fn init(boot_info: &'static BootInfo) {
     //Interupts Initilization
+    smp::set_current_cpu(0);
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
@@ -114,13 +127,25 @@ pub fn init(boot_info: &'static BootInfo) {
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    *memory::FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    log::init();
 
     crate::io::SCANCODE_QUEUE
         .try_init_once(|| ArrayQueue::new(100))
         .expect("ScancodeQueue already initialized");
+    if let Err(err) = io::init_keyboard() {
+        warn!("keyboard initialization failed: {:?}", err);
+    }
 
-    io::init_mouse();
+    if let Err(err) = io::init_mouse() {
+        warn!("no PS/2 mouse: {:?}", err);
+    }
+    io::init_serial();
 
     //Graphics Initilization
     VGA.lock().setup();
+
+    //Threads - from here on the timer preempts whatever is running
+    thread::init();
+    smp::init();
 }
@@ -0,0 +1,369 @@
+use crate::graphics::Vga;
+use crate::sync::IrqSafeMutex;
+use crate::{io, serial_print, timer};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+/// How important a message is, `Error` being the most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// The least important level that still gets logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LevelFilter {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LevelFilter {
+    pub fn allows(self, level: Level) -> bool {
+        level as u8 <= self as u8
+    }
+}
+
+/// One log message as the sinks get it
+pub struct Record<'a> {
+    pub level: Level,
+    /// module_path!() of the code that logged it
+    pub module: &'a str,
+    /// timer ticks since boot
+    pub ticks: usize,
+    pub args: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:>8}] {:<5} {}: {}",
+            self.ticks, self.level, self.module, self.args
+        )
+    }
+}
+
+/// Somewhere log messages end up. Sinks are called with interrupts disabled and may be called from interrupt handlers.
+pub trait Sink: Send {
+    fn log(&mut self, record: &Record);
+}
+
+struct Filters {
+    default: LevelFilter,
+    //module path prefix and its level, the longest matching prefix wins
+    modules: Vec<(&'static str, LevelFilter)>,
+}
+
+impl Filters {
+    fn level_for(&self, module: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| {
+                module == *prefix
+                    || (module.starts_with(prefix) && module[prefix.len()..].starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
+
+static FILTERS: IrqSafeMutex<Filters> = IrqSafeMutex::new(
+    "log::FILTERS",
+    Filters {
+        default: LevelFilter::Info,
+        modules: Vec::new(),
+    },
+);
+static SINKS: IrqSafeMutex<Vec<Box<dyn Sink>>> = IrqSafeMutex::new("log::SINKS", Vec::new());
+
+/// Sends everything to the serial port and the ring buffer. Until then messages go straight to the serial port.
+pub fn init() {
+    add_sink(Box::new(SerialSink));
+    add_sink(Box::new(RingBufferSink));
+}
+
+pub fn add_sink(sink: Box<dyn Sink>) {
+    SINKS.lock().push(sink);
+}
+
+/// Level for every module without its own filter
+pub fn set_level(level: LevelFilter) {
+    FILTERS.lock().default = level;
+}
+
+/// Level for `module` and its submodules, e.g. `set_module_level("finn_os::thread", LevelFilter::Trace)`
+pub fn set_module_level(module: &'static str, level: LevelFilter) {
+    let mut filters = FILTERS.lock();
+    match filters
+        .modules
+        .iter_mut()
+        .find(|(prefix, _)| *prefix == module)
+    {
+        Some(filter) => filter.1 = level,
+        None => filters.modules.push((module, level)),
+    }
+}
+
+/// Removes the filter of `module`, so it uses the level of its parent again
+pub fn clear_module_level(module: &str) {
+    FILTERS
+        .lock()
+        .modules
+        .retain(|(prefix, _)| *prefix != module);
+}
+
+pub fn enabled(level: Level, module: &str) -> bool {
+    FILTERS.lock().level_for(module).allows(level)
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module: &'static str, args: fmt::Arguments) {
+    if !enabled(level, module) {
+        return;
+    }
+    let record = Record {
+        level,
+        module,
+        ticks: timer::ticks(),
+        args,
+    };
+    let mut sinks = SINKS.lock();
+    if sinks.is_empty() {
+        SerialSink.log(&record);
+    }
+    for sink in sinks.iter_mut() {
+        sink.log(&record);
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::log::_log($level, module_path!(), format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Error, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Warn, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Info, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Debug, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Trace, $($arg)*) };
+}
+
+/// Writes every message to COM1
+pub struct SerialSink;
+
+impl Sink for SerialSink {
+    fn log(&mut self, record: &Record) {
+        serial_print!("{}\n", record);
+    }
+}
+
+const RING_BUFFER_SIZE: usize = 16 * 1024;
+
+//the oldest output is overwritten once it's full
+struct RingBuffer {
+    bytes: [u8; RING_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    fn push(&mut self, byte: u8) {
+        let end = (self.start + self.len) % RING_BUFFER_SIZE;
+        self.bytes[end] = byte;
+        if self.len == RING_BUFFER_SIZE {
+            self.start = (self.start + 1) % RING_BUFFER_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    //the contents in order, as the two halves on either side of the wrap around
+    fn as_slices(&self) -> (&[u8], &[u8]) {
+        let end = self.start + self.len;
+        if end <= RING_BUFFER_SIZE {
+            (&self.bytes[self.start..end], &[])
+        } else {
+            (
+                &self.bytes[self.start..],
+                &self.bytes[..end - RING_BUFFER_SIZE],
+            )
+        }
+    }
+}
+
+impl Write for RingBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
+
+static RING_BUFFER: IrqSafeMutex<RingBuffer> = IrqSafeMutex::new(
+    "log::RING_BUFFER",
+    RingBuffer {
+        bytes: [0; RING_BUFFER_SIZE],
+        start: 0,
+        len: 0,
+    },
+);
+
+/// Keeps the last 16KiB of messages in memory, see `dump_ring_buffer`
+pub struct RingBufferSink;
+
+impl Sink for RingBufferSink {
+    fn log(&mut self, record: &Record) {
+        let _ = writeln!(RING_BUFFER.lock(), "{}", record);
+    }
+}
+
+/// Everything that is in the ring buffer
+pub fn ring_buffer_contents() -> String {
+    let ring_buffer = RING_BUFFER.lock();
+    let (first, second) = ring_buffer.as_slices();
+    let mut contents = String::from_utf8_lossy(first).into_owned();
+    contents.push_str(&String::from_utf8_lossy(second));
+    contents
+}
+
+/// Writes the ring buffer straight to COM1. Meant for panic handlers, so it neither allocates nor waits for a lock and
+/// goes around a `SerialReservation` and the capture. Does nothing if the port is locked.
+pub fn dump_ring_buffer() {
+    let mut serial = match io::SERIAL.try_lock() {
+        Some(serial) => serial,
+        None => return,
+    };
+    let mut send = |bytes: &[u8]| bytes.iter().for_each(|byte| serial.send_raw(*byte));
+    match RING_BUFFER.try_lock() {
+        Some(ring_buffer) => {
+            let (first, second) = ring_buffer.as_slices();
+            send(b"---- log ring buffer ----\n");
+            send(first);
+            send(second);
+            send(b"---- end of log ring buffer ----\n");
+        }
+        None => send(b"log ring buffer is locked, can't dump it\n"),
+    }
+}
+
+const CONSOLE_COLUMNS: usize = 40; //320 pixels of 8x8 characters
+const CONSOLE_ROWS: usize = 6;
+
+//the last lines that were logged to the screen, next is the oldest one
+struct Console {
+    lines: [[u8; CONSOLE_COLUMNS]; CONSOLE_ROWS],
+    lengths: [usize; CONSOLE_ROWS],
+    next: usize,
+}
+
+impl Console {
+    fn new_line(&mut self) {
+        self.lengths[self.next] = 0;
+        self.next = (self.next + 1) % CONSOLE_ROWS;
+    }
+}
+
+impl Write for Console {
+    //only ascii is kept, longer lines are cut off
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let line = (self.next + CONSOLE_ROWS - 1) % CONSOLE_ROWS;
+        for byte in s.bytes() {
+            let length = self.lengths[line];
+            if byte.is_ascii() && !byte.is_ascii_control() && length < CONSOLE_COLUMNS {
+                self.lines[line][length] = byte;
+                self.lengths[line] += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+static CONSOLE: IrqSafeMutex<Console> = IrqSafeMutex::new(
+    "log::CONSOLE",
+    Console {
+        lines: [[0; CONSOLE_COLUMNS]; CONSOLE_ROWS],
+        lengths: [0; CONSOLE_ROWS],
+        next: 0,
+    },
+);
+
+/// Shows the latest messages at `level` or more important ones at the top of the screen, see `draw_console`
+pub struct ScreenSink {
+    level: LevelFilter,
+}
+
+impl ScreenSink {
+    pub fn new(level: LevelFilter) -> Self {
+        Self { level }
+    }
+}
+
+impl Sink for ScreenSink {
+    fn log(&mut self, record: &Record) {
+        if !self.level.allows(record.level) {
+            return;
+        }
+        let mut console = CONSOLE.lock();
+        console.new_line();
+        let _ = write!(console, "{:<5} {}", record.level, record.args);
+    }
+}
+
+/// Draws the screen console into the back buffer, oldest line first
+pub fn draw_console(vga: &mut Vga, color: u8) {
+    let console = CONSOLE.lock();
+    for row in 0..CONSOLE_ROWS {
+        let line = (console.next + row) % CONSOLE_ROWS;
+        for (column, byte) in console.lines[line][..console.lengths[line]]
+            .iter()
+            .enumerate()
+        {
+            vga.draw_character(column * 8, row * 8, char::from(*byte), color);
+        }
+    }
+}
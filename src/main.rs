@@ -6,10 +6,15 @@
 
 extern crate alloc;
 
+use alloc::boxed::Box;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use finn_os::executor::{Executor, Task};
+use finn_os::gdb;
+use finn_os::io;
+use finn_os::log::{self, LevelFilter, ScreenSink};
 use finn_os::render::render;
+use finn_os::thread;
 
 entry_point!(kernel_main);
 
@@ -19,17 +24,31 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     #[cfg(test)]
     test_main();
 
+    log::add_sink(Box::new(ScreenSink::new(LevelFilter::Warn)));
+    //breakpoints wait for gdb on COM2 from here on, e.g. `target remote /dev/pts/N` with `-serial pty`
+    if gdb::init() {
+        finn_os::info!("gdb stub listening on COM2");
+    }
+
+    io::register_builtin_hotkeys();
+
     let mut executor = Executor::new();
-    executor.spawn(Task::new(render()));
+    executor.spawn(Task::new(render()).with_name("render"));
+    executor.spawn(Task::new(io::keyboard_task()).with_name("keyboard"));
 
-    executor.run();
+    //the executor is just one more thread, so a task that never awaits can't freeze the kernel
+    thread::spawn(move || executor.run());
+    thread::exit();
 }
 /// This function is called on panic.
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     use finn_os::serial_println;
+    //a screenshot that is being sent would hold the message back forever
+    io::SerialReservation::cancel();
     serial_println!("{}", info);
+    log::dump_ring_buffer();
     finn_os::hlt_loop();
 }
 
@@ -1,7 +1,10 @@
+use crate::sync::IrqSafeMutex;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use conquer_once::spin::OnceCell;
 use x86_64::registers::control::Cr3;
+use x86_64::registers::model_specific::{Efer, EferFlags};
 use x86_64::{
-    structures::paging::{FrameAllocator, PageTable, Size4KiB},
+    structures::paging::{FrameAllocator, FrameDeallocator, PageTable, PageTableFlags, Size4KiB},
     structures::paging::{OffsetPageTable, PhysFrame},
     PhysAddr, VirtAddr,
 };
@@ -16,14 +19,143 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_ptr // unsafe - all phys mem must be loaded after physical_memory_offest arg
 }
 
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Frame allocator shared by everything that maps memory after boot. Holding the lock also serializes changes to the page tables.
+pub static FRAME_ALLOCATOR: IrqSafeMutex<Option<BootInfoFrameAllocator>> =
+    IrqSafeMutex::new("memory::FRAME_ALLOCATOR", None);
+
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.init_once(|| physical_memory_offset);
+    //without it the NO_EXECUTE bit is reserved and using it faults
+    Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+/// Virtual address at which the bootloader mapped all of physical memory
+pub fn physical_memory_offset() -> VirtAddr {
+    *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("memory::init has not been called")
+}
+
+/// Returns a mapper for the active page table.
+///
+/// Unsafe b.c. every mapper holds a mutable reference to the same table - the caller must make sure they are not used at the same time (e.g. by holding FRAME_ALLOCATOR)
+pub unsafe fn active_mapper() -> OffsetPageTable<'static> {
+    let offset = physical_memory_offset();
+    OffsetPageTable::new(active_level_4_table(offset), offset)
+}
+
+/// Runs `f` with the frame allocator locked, which also keeps interrupts disabled
+pub fn with_frame_allocator<R>(f: impl FnOnce(&mut BootInfoFrameAllocator) -> R) -> R {
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    f(frame_allocator
+        .as_mut()
+        .expect("memory::init has not been called"))
+}
+
+/// Level 4 page table for a user program. The kernel entries are shared with the page table
+/// that was active on creation, the user space entries start out empty.
+///
+/// Dropping it frees every user frame and page table, so it must not be active anymore at that point.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    /// Returns None if there is no frame left for the level 4 table
+    pub fn new() -> Option<Self> {
+        let level_4_frame =
+            with_frame_allocator(|frame_allocator| frame_allocator.allocate_frame())?;
+
+        unsafe {
+            let active = active_level_4_table(physical_memory_offset());
+            let table = table_at(level_4_frame);
+            *table = PageTable::new();
+            for (i, entry) in active.iter().enumerate() {
+                if !is_user_entry(i) {
+                    table[i] = entry.clone();
+                }
+            }
+        }
+        Some(Self { level_4_frame })
+    }
+
+    /// The frame to load into CR3 to switch to this address space
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns a mapper for this address space, it doesn't have to be active.
+    ///
+    /// Unsafe for the same reasons as active_mapper
+    pub unsafe fn mapper(&mut self) -> OffsetPageTable<'static> {
+        OffsetPageTable::new(table_at(self.level_4_frame), physical_memory_offset())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert_ne!(
+            Cr3::read().0,
+            self.level_4_frame,
+            "dropping the active address space"
+        );
+        with_frame_allocator(|frame_allocator| unsafe {
+            let table = table_at(self.level_4_frame);
+            for (i, entry) in table.iter().enumerate() {
+                if is_user_entry(i) && entry.flags().contains(PageTableFlags::PRESENT) {
+                    free_table(entry.addr(), 3, frame_allocator);
+                }
+            }
+            frame_allocator.deallocate_frame(self.level_4_frame);
+        });
+    }
+}
+
+//Frees the table at `addr` together with everything it maps, level 1 tables map the user's frames directly
+unsafe fn free_table(addr: PhysAddr, level: u8, frame_allocator: &mut BootInfoFrameAllocator) {
+    let frame = PhysFrame::containing_address(addr);
+    for entry in table_at(frame).iter() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        //user space is only ever mapped with 4KiB pages
+        if level == 1 {
+            frame_allocator.deallocate_frame(PhysFrame::containing_address(entry.addr()));
+        } else {
+            free_table(entry.addr(), level - 1, frame_allocator);
+        }
+    }
+    frame_allocator.deallocate_frame(frame);
+}
+
+unsafe fn table_at(frame: PhysFrame) -> &'static mut PageTable {
+    let virt = physical_memory_offset() + frame.start_address().as_u64();
+    &mut *virt.as_mut_ptr()
+}
+
+fn is_user_entry(index: usize) -> bool {
+    let first = VirtAddr::new(crate::usermode::USER_SPACE_START).p4_index();
+    let last = VirtAddr::new(crate::usermode::USER_SPACE_END - 1).p4_index();
+    (usize::from(first)..=usize::from(last)).contains(&index)
+}
+
+//frames below this are left for real mode code
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+//marks the last frame in the free list, frame 0 is a valid address
+const LIST_END: u64 = u64::MAX;
+
+/// Hands out the usable frames from the memory map in order. Freed frames are kept in a list that is
+/// threaded through the frames themselves and get reused first.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    free_list: Option<PhysFrame>,
+    free_count: usize,
 }
 
 impl BootInfoFrameAllocator {
@@ -31,6 +163,8 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            free_list: None,
+            free_count: 0,
         }
     }
 
@@ -39,14 +173,54 @@ impl BootInfoFrameAllocator {
         let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
         let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
         let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+        frame_addresses
+            .filter(|addr| *addr >= LOW_MEMORY_END)
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// A usable frame below 1MiB, which the allocator never hands out. Needed for code that starts in real mode.
+    pub fn low_memory_frame(&self) -> Option<PhysFrame> {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .flat_map(|r| (r.range.start_addr()..r.range.end_addr()).step_by(4096))
+            .find(|addr| *addr != 0 && *addr < LOW_MEMORY_END)
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// Number of frames that are currently handed out
+    pub fn used_frames(&self) -> usize {
+        self.next - self.free_count
+    }
+
+    //the first 8 bytes of a free frame hold the address of the next one
+    unsafe fn next_link(frame: PhysFrame) -> *mut u64 {
+        (physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr()
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free_list {
+            let next = unsafe { Self::next_link(frame).read() };
+            self.free_list =
+                (next != LIST_END).then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+            self.free_count -= 1;
+            return Some(frame);
+        }
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
 }
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let next = self
+            .free_list
+            .map_or(LIST_END, |next| next.start_address().as_u64());
+        Self::next_link(frame).write(next);
+        self.free_list = Some(frame);
+        self.free_count += 1;
+    }
+}
@@ -0,0 +1,149 @@
+use crate::elf::{self, ElfError};
+use crate::memory::AddressSpace;
+use crate::sync::IrqSafeMutex;
+use crate::thread::{self, ThreadId};
+use crate::usermode;
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    fn new() -> Self {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(i64),
+    Killed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    /// Ended, but nobody has waited for it yet
+    Zombie(ExitStatus),
+}
+
+/// A user program running on its own thread in its own address space
+struct Process {
+    thread: ThreadId,
+    state: ProcessState,
+    //shared with the thread so the scheduler can switch CR3, the user frames are freed once both are gone
+    _address_space: Arc<AddressSpace>,
+}
+
+static PROCESSES: IrqSafeMutex<BTreeMap<Pid, Process>> =
+    IrqSafeMutex::new("process::PROCESSES", BTreeMap::new());
+
+//syscalls touch the table from inside an interrupt, the lock keeps interrupts disabled while it's held
+fn with_processes<R>(f: impl FnOnce(&mut BTreeMap<Pid, Process>) -> R) -> R {
+    f(&mut PROCESSES.lock())
+}
+
+/// Loads the ELF executable `data` and starts it with `args` as its argv
+pub fn spawn(data: &[u8], args: &[&str]) -> Result<Pid, ElfError> {
+    let program = elf::load_program(data, args)?;
+    let address_space = Arc::new(program.address_space);
+    let (entry, stack_pointer) = (program.entry, program.stack_pointer);
+
+    let pid = Pid::new();
+    //the new thread could exit before it is in the table otherwise
+    with_processes(|processes| {
+        let thread = thread::spawn_in(address_space.clone(), move || {
+            usermode::enter(entry, stack_pointer)
+        });
+        processes.insert(
+            pid,
+            Process {
+                thread,
+                state: ProcessState::Running,
+                _address_space: address_space,
+            },
+        );
+    });
+    Ok(pid)
+}
+
+/// The process the current thread belongs to, None for kernel threads
+pub fn current() -> Option<Pid> {
+    let thread = thread::current();
+    with_processes(|processes| {
+        processes
+            .iter()
+            .find(|(_, process)| process.thread == thread)
+            .map(|(pid, _)| *pid)
+    })
+}
+
+pub fn state(pid: Pid) -> Option<ProcessState> {
+    with_processes(|processes| processes.get(&pid).map(|process| process.state))
+}
+
+/// Ends the current process with `code`
+pub fn exit(code: i64) -> ! {
+    mark_exited(code);
+    thread::exit();
+}
+
+/// Records the exit code and marks the thread dead, the switch happens on the next schedule - used by the exit syscall.
+/// Threads that entered ring 3 without being a process just end.
+pub(crate) fn mark_exited(code: i64) {
+    let thread = thread::current();
+    with_processes(|processes| {
+        let process = processes
+            .values_mut()
+            .find(|process| process.thread == thread);
+        if let Some(process) = process {
+            process.state = ProcessState::Zombie(ExitStatus::Exited(code));
+        }
+    });
+    thread::mark_dead();
+}
+
+/// Ends `pid` without running any more of its code. Returns false if there is no such running process.
+pub fn kill(pid: Pid) -> bool {
+    let thread = with_processes(|processes| match processes.get_mut(&pid) {
+        Some(process) if process.state == ProcessState::Running => {
+            process.state = ProcessState::Zombie(ExitStatus::Killed);
+            Some(process.thread)
+        }
+        _ => None,
+    });
+    match thread {
+        Some(thread) => {
+            thread::kill(thread);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Blocks until `pid` has ended and removes it, which frees its address space.
+/// Returns None if there is no such process or somebody else already waited for it.
+pub fn wait(pid: Pid) -> Option<ExitStatus> {
+    let thread = with_processes(|processes| processes.get(&pid).map(|process| process.thread))?;
+    //the scheduler drops its reference to the address space when it frees the thread
+    thread::wait_freed(thread);
+    let process = with_processes(|processes| processes.remove(&pid))?;
+    match process.state {
+        ProcessState::Zombie(status) => Some(status),
+        //exit and kill record the status before the thread ends, so this can't be seen here
+        ProcessState::Running => Some(ExitStatus::Killed),
+    }
+}
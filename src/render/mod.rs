@@ -2,4 +2,4 @@ mod geometry;
 mod objects;
 mod renderer;
 
-pub use renderer::render;
+pub use renderer::{render, toggle_wireframe};
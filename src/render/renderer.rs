@@ -1,14 +1,26 @@
 use super::geometry::*;
 use super::objects::SHIP;
 use crate::graphics::VGA;
-use crate::io::{get_key_ev, KeyCode, KeyEvent, KeyState, MOUSE, SCANCODE_QUEUE};
+use crate::io::{pressed_keys, KeyCode, MOUSE};
+use crate::log;
 use crate::timer::sleep;
 use alloc::vec::Vec;
 use core::f32::consts::PI;
+use core::sync::atomic::{AtomicBool, Ordering};
 use libm::tanf;
 
 const WIDTH: f32 = 320.0;
 const HEIGHT: f32 = 200.0;
+//radians the camera turns per unit of mouse motion, tuned with Mouse::set_sensitivity
+const LOOK_SPEED: f32 = 0.02;
+
+//outlines instead of filled triangles, for debugging
+static WIREFRAME: AtomicBool = AtomicBool::new(false);
+
+/// Switches between filled triangles and their outlines
+pub fn toggle_wireframe() {
+    WIREFRAME.fetch_xor(true, Ordering::Relaxed);
+}
 
 pub async fn render() {
     let mesh = Mesh::from_obj_file(SHIP);
@@ -38,78 +50,17 @@ pub async fn render() {
     let mut yaw: f32 = 0.0;
     let mut pitch: f32 = 0.0;
 
-    let scancode_queue = SCANCODE_QUEUE.try_get().unwrap();
-
-    //Don't want to register input on every press - this only works if you have a key repeat rate
-    // Instead increment every frame based on the following variables
-    let mut w_pressed = false;
-    let mut a_pressed = false;
-    let mut s_pressed = false;
-    let mut d_pressed = false;
-
     let mut iterations: f32 = 0.0;
     loop {
         // Get user input
-        while let Ok(code) = scancode_queue.pop() {
-            if let Ok(key_event) = get_key_ev(code) {
-                match key_event {
-                    KeyEvent {
-                        code: KeyCode::W,
-                        state: KeyState::Down,
-                    } => {
-                        w_pressed = true;
-                    }
-                    KeyEvent {
-                        code: KeyCode::W,
-                        state: KeyState::Up,
-                    } => {
-                        w_pressed = false;
-                    }
-                    KeyEvent {
-                        code: KeyCode::A,
-                        state: KeyState::Down,
-                    } => {
-                        a_pressed = true;
-                    }
-                    KeyEvent {
-                        code: KeyCode::A,
-                        state: KeyState::Up,
-                    } => {
-                        a_pressed = false;
-                    }
-                    KeyEvent {
-                        code: KeyCode::S,
-                        state: KeyState::Down,
-                    } => {
-                        s_pressed = true;
-                    }
-                    KeyEvent {
-                        code: KeyCode::S,
-                        state: KeyState::Up,
-                    } => {
-                        s_pressed = false;
-                    }
-                    KeyEvent {
-                        code: KeyCode::D,
-                        state: KeyState::Down,
-                    } => {
-                        d_pressed = true;
-                    }
-                    KeyEvent {
-                        code: KeyCode::D,
-                        state: KeyState::Up,
-                    } => {
-                        d_pressed = false;
-                    }
-                    _ => {}
-                }
-            }
-        }
+        //held keys move the camera every frame, so movement doesn't depend on the key repeat
+        let pressed = pressed_keys();
+        let wireframe = WIREFRAME.load(Ordering::Relaxed);
 
-        if w_pressed {
+        if pressed.contains(KeyCode::W) {
             camera_vector = Vector::add(&camera_vector, &look_direction);
         }
-        if s_pressed {
+        if pressed.contains(KeyCode::S) {
             camera_vector = Vector::sub(&camera_vector, &look_direction);
         }
         let opp_look_direction = Vector {
@@ -118,16 +69,16 @@ pub async fn render() {
             z: -look_direction.x,
             w: 1.0,
         };
-        if a_pressed {
+        if pressed.contains(KeyCode::A) {
             camera_vector = Vector::add(&camera_vector, &opp_look_direction);
         }
-        if d_pressed {
+        if pressed.contains(KeyCode::D) {
             camera_vector = Vector::sub(&camera_vector, &opp_look_direction);
         }
 
-        let (delta_x, delta_y) = MOUSE.lock().get_coords();
-        yaw += delta_x as f32 * 0.02;
-        pitch += delta_y as f32 * 0.02;
+        let (motion_x, motion_y) = MOUSE.lock().take_motion();
+        yaw += motion_x * LOOK_SPEED;
+        pitch += motion_y * LOOK_SPEED;
 
         //Compute world matrix (rotation and translation)
         let theta: f32 = 1.0 * iterations;
@@ -354,19 +305,21 @@ pub async fn render() {
             }
 
             for tri in triangle_queue {
-                VGA.lock().fill_triangle(
-                    (tri.p[0].x as isize, tri.p[0].y as isize),
-                    (tri.p[1].x as isize, tri.p[1].y as isize),
-                    (tri.p[2].x as isize, tri.p[2].y as isize),
-                    tri.color,
-                );
-                // WIREFRAME FOR DEBUGGING
-                // VGA.lock().draw_triangle(
-                //     (tri.p[0].x as isize, tri.p[0].y as isize),
-                //     (tri.p[1].x as isize, tri.p[1].y as isize),
-                //     (tri.p[2].x as isize, tri.p[2].y as isize),
-                //     0x0F,
-                // )
+                if wireframe {
+                    VGA.lock().draw_triangle(
+                        (tri.p[0].x as isize, tri.p[0].y as isize),
+                        (tri.p[1].x as isize, tri.p[1].y as isize),
+                        (tri.p[2].x as isize, tri.p[2].y as isize),
+                        0x0F,
+                    );
+                } else {
+                    VGA.lock().fill_triangle(
+                        (tri.p[0].x as isize, tri.p[0].y as isize),
+                        (tri.p[1].x as isize, tri.p[1].y as isize),
+                        (tri.p[2].x as isize, tri.p[2].y as isize),
+                        tri.color,
+                    );
+                }
             }
         }
 
@@ -397,7 +350,10 @@ pub async fn render() {
         );
 
         // Swap buffers
-        VGA.lock().swap_buffers();
+        let mut vga = VGA.lock();
+        log::draw_console(&mut vga, 0xF);
+        vga.swap_buffers();
+        drop(vga);
 
         sleep(1).await;
         iterations += 0.05;
@@ -0,0 +1,312 @@
+use crate::interrupts::LAPIC_TIMER_VECTOR;
+use crate::{acpi, apic, gdt, interrupts, memory, thread, timer, warn};
+use alloc::vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::Cr3;
+use x86_64::registers::model_specific::KernelGsBase;
+use x86_64::structures::paging::{
+    mapper::MapToError, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+pub const MAX_CPUS: usize = 16;
+const AP_STACK_SIZE: usize = 4096 * 16;
+
+/// Per-cpu bookkeeping, see `this_cpu`
+pub struct CpuData {
+    apic_id: AtomicU8,
+    online: AtomicBool,
+    /// local APIC timer interrupts handled by this cpu
+    pub local_ticks: AtomicUsize,
+}
+
+impl CpuData {
+    const fn new() -> Self {
+        Self {
+            apic_id: AtomicU8::new(0),
+            online: AtomicBool::new(false),
+            local_ticks: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn apic_id(&self) -> u8 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+}
+
+const OFFLINE: CpuData = CpuData::new();
+static CPUS: [CpuData; MAX_CPUS] = [OFFLINE; MAX_CPUS];
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+//local APIC timer count per PIT tick, so every cpu gets time slices of the same length
+static TIMER_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Number of cpus that are running
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Index of the cpu that is running this, 0 is the bootstrap cpu
+pub fn current_cpu() -> usize {
+    KernelGsBase::read().as_u64() as usize
+}
+
+//Every cpu stores its index before it takes its first lock. swapgs is never used, so KERNEL_GS_BASE is free to hold it -
+//unlike the GS base, ring 3 can't change it by loading a segment register.
+pub(crate) fn set_current_cpu(cpu: usize) {
+    KernelGsBase::write(VirtAddr::new(cpu as u64));
+}
+
+pub fn cpu(index: usize) -> &'static CpuData {
+    &CPUS[index]
+}
+
+pub fn this_cpu() -> &'static CpuData {
+    &CPUS[current_cpu()]
+}
+
+// The application processors start in real mode at the start of a page below 1MiB. The trampoline is copied there,
+// loads a temporary GDT, the kernel page table and jumps straight into long mode and to `ap_entry`.
+// Everything it needs is in the params block at its end, see TrampolineParams.
+core::arch::global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_long_mode",
+    ".global ap_trampoline_params",
+    ".global ap_trampoline_end",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    "lgdt [ap_trampoline_params_offset + 62]",
+    "mov eax, cr4",
+    "or eax, 0x20", // physical address extension
+    "mov cr4, eax",
+    "mov eax, dword ptr [ap_trampoline_params_offset]",
+    "mov cr3, eax",
+    "mov ecx, 0xC0000080", // EFER
+    "rdmsr",
+    "or eax, 0x900", // long mode + no-execute enable
+    "wrmsr",
+    "mov eax, cr0",
+    "or eax, 0x80010001", // paging + write protect + protected mode
+    "mov cr0, eax",
+    // jmp fword ptr [params + 56] - spelled out since the assembler leaves out the 32 bit operand size prefix
+    ".byte 0x66, 0xff, 0x2e",
+    ".word ap_trampoline_params_offset + 56",
+    ".code64",
+    "ap_trampoline_long_mode:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov rsp, [rip + ap_trampoline_params + 8]",
+    "mov rdi, [rip + ap_trampoline_params + 24]",
+    "mov rax, [rip + ap_trampoline_params + 16]",
+    "call rax",
+    "ud2",
+    ".align 8",
+    "ap_trampoline_params:",
+    ".skip 72",
+    "ap_trampoline_end:",
+    ".set ap_trampoline_params_offset, ap_trampoline_params - ap_trampoline_start",
+    ".popsection",
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Layout of the params block at the end of the trampoline, the offsets are hardcoded in the assembly above
+#[repr(C)]
+struct TrampolineParams {
+    cr3: u64,
+    stack_top: u64,
+    entry: u64,
+    cpu: u64,
+    gdt: [u64; 3],
+    //far pointer for the jump into long mode
+    long_mode_offset: u32,
+    long_mode_selector: u16,
+    //operand of lgdt
+    gdt_limit: u16,
+    gdt_base: u64,
+}
+
+//null, 64 bit code, data
+const TRAMPOLINE_GDT: [u64; 3] = [0, 0x00AF_9A00_0000_FFFF, 0x00CF_9200_0000_FFFF];
+
+/// Starts every usable processor from the MADT. Without a MADT the kernel just keeps running on the bootstrap cpu.
+///
+/// Has to be called after `thread::init` since the new cpus go straight into the scheduler.
+pub fn init() {
+    let madt = match acpi::find_madt() {
+        Some(madt) => madt,
+        None => return,
+    };
+    if madt.processors.iter().filter(|p| p.usable).count() < 2 {
+        return;
+    }
+    //current_cpu looks the bootstrap cpu up by its APIC id as soon as the APIC is mapped
+    let bsp_apic_id = x86_64::instructions::interrupts::without_interrupts(|| {
+        apic::init(madt.local_apic_address).expect("failed to map the local APIC");
+        let bsp_apic_id = apic::id();
+        CPUS[0].apic_id.store(bsp_apic_id, Ordering::Relaxed);
+        bsp_apic_id
+    });
+    CPUS[0].online.store(true, Ordering::Release);
+    TIMER_COUNT.store(measure_timer(), Ordering::Relaxed);
+
+    let trampoline = match prepare_trampoline() {
+        Some(trampoline) => trampoline,
+        None => {
+            warn!("no memory below 1MiB for the trampoline");
+            return;
+        }
+    };
+
+    let application_processors = madt
+        .processors
+        .iter()
+        .filter(|p| p.usable && p.apic_id != bsp_apic_id);
+    for processor in application_processors {
+        let cpu = cpu_count();
+        if cpu == MAX_CPUS {
+            break;
+        }
+        if !start_ap(cpu, processor.apic_id, trampoline) {
+            warn!("cpu with APIC id {} did not start", processor.apic_id);
+        }
+    }
+}
+
+//how many local APIC timer ticks fit into one PIT tick
+fn measure_timer() -> u32 {
+    wait_for_tick();
+    apic::start_measuring_timer();
+    wait_for_tick();
+    apic::measured_timer_count()
+}
+
+fn wait_for_tick() {
+    let start = timer::ticks();
+    while timer::ticks() == start {
+        x86_64::instructions::hlt();
+    }
+}
+
+//roughly a microsecond per port write
+fn delay_us(us: usize) {
+    let mut port: Port<u8> = Port::new(0x80);
+    for _ in 0..us {
+        unsafe { port.write(0) };
+    }
+}
+
+//Copies the trampoline code into a low frame and identity maps it, since it keeps running from there once paging is on
+fn prepare_trampoline() -> Option<PhysFrame> {
+    let frame = memory::with_frame_allocator(|frame_allocator| {
+        let frame = frame_allocator.low_memory_frame()?;
+        let page =
+            Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+        let mut mapper = unsafe { memory::active_mapper() };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(MapToError::PageAlreadyMapped(_))
+                if mapper.translate_addr(page.start_address()) == Some(frame.start_address()) => {}
+            Err(_) => return None,
+        }
+        Some(frame)
+    })?;
+
+    unsafe {
+        let start = &ap_trampoline_start as *const u8;
+        let len = &ap_trampoline_end as *const u8 as usize - start as usize;
+        assert!(len <= 4096, "trampoline does not fit into a page");
+        let target =
+            (memory::physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr();
+        core::ptr::copy_nonoverlapping(start, target, len);
+    }
+    Some(frame)
+}
+
+fn start_ap(cpu: usize, apic_id: u8, trampoline: PhysFrame) -> bool {
+    let base = trampoline.start_address().as_u64();
+    let offset_of =
+        |symbol: *const u8| unsafe { symbol as u64 - &ap_trampoline_start as *const u8 as u64 };
+    let params_offset = offset_of(unsafe { &ap_trampoline_params });
+    let long_mode_offset = offset_of(unsafe { &ap_trampoline_long_mode });
+
+    //the trampoline only loads the low 32 bits of CR3
+    let cr3 = Cr3::read().0.start_address().as_u64();
+    assert!(cr3 < 1 << 32, "kernel page table is above 4GiB");
+    let stack = vec![0u8; AP_STACK_SIZE].leak();
+    let stack_top = (stack.as_ptr() as u64 + AP_STACK_SIZE as u64) & !0xF;
+
+    let params = TrampolineParams {
+        cr3,
+        stack_top,
+        entry: ap_entry as *const () as u64,
+        cpu: cpu as u64,
+        gdt: TRAMPOLINE_GDT,
+        long_mode_offset: (base + long_mode_offset) as u32,
+        long_mode_selector: 0x08,
+        gdt_limit: (size_of::<[u64; 3]>() - 1) as u16,
+        gdt_base: base + params_offset + 32,
+    };
+    unsafe {
+        let target = memory::physical_memory_offset() + base + params_offset;
+        target.as_mut_ptr::<TrampolineParams>().write(params);
+    }
+
+    CPUS[cpu].apic_id.store(apic_id, Ordering::Relaxed);
+    //INIT, then two startup IPIs as the MP spec asks for
+    apic::send_init(apic_id);
+    wait_for_tick();
+    let page = (base / 4096) as u8;
+    for _ in 0..2 {
+        apic::send_startup(apic_id, page);
+        delay_us(200);
+        if CPUS[cpu].is_online() {
+            break;
+        }
+    }
+
+    let start = timer::ticks();
+    while !CPUS[cpu].is_online() {
+        if timer::ticks() - start > 10 {
+            return false;
+        }
+        x86_64::instructions::hlt();
+    }
+    true
+}
+
+extern "C" fn ap_entry(cpu: usize) -> ! {
+    set_current_cpu(cpu);
+    gdt::init_ap(cpu);
+    interrupts::init_idt();
+    apic::enable();
+
+    thread::init_ap(cpu);
+    CPU_COUNT.fetch_add(1, Ordering::AcqRel);
+    CPUS[cpu].online.store(true, Ordering::Release);
+
+    apic::start_periodic_timer(LAPIC_TIMER_VECTOR, TIMER_COUNT.load(Ordering::Relaxed));
+    x86_64::instructions::interrupts::enable();
+    //this is the idle thread of the cpu now, the timer switches to real work
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
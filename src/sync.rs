@@ -0,0 +1,110 @@
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+
+/// Spinlock that keeps interrupts disabled while it is held, so an interrupt handler taking the same lock can't
+/// deadlock against the code it interrupted. The interrupt flag is restored to what it was once the guard is dropped.
+///
+/// With debug assertions, locking it again on the cpu that already holds it panics with the lock's name instead of spinning forever.
+pub struct IrqSafeMutex<T> {
+    name: &'static str,
+    //index + 1 of the cpu holding the lock, 0 if nobody does
+    owner: AtomicUsize,
+    inner: spin::Mutex<T>,
+}
+
+impl<T> IrqSafeMutex<T> {
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            owner: AtomicUsize::new(0),
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        if cfg!(debug_assertions) {
+            let cpu = crate::smp::current_cpu();
+            if self.owner.load(Ordering::Relaxed) == cpu + 1 {
+                panic!("lock {} re-entered on cpu {}", self.name, cpu);
+            }
+        }
+        let guard = self.inner.lock();
+        self.set_owner();
+        IrqSafeMutexGuard {
+            lock: self,
+            guard: Some(guard),
+            interrupts_enabled,
+        }
+    }
+
+    /// Returns None if the lock is held, without spinning
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<'_, T>> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                self.set_owner();
+                Some(IrqSafeMutexGuard {
+                    lock: self,
+                    guard: Some(guard),
+                    interrupts_enabled,
+                })
+            }
+            None => {
+                if interrupts_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    fn set_owner(&self) {
+        if cfg!(debug_assertions) {
+            self.owner
+                .store(crate::smp::current_cpu() + 1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct IrqSafeMutexGuard<'a, T> {
+    lock: &'a IrqSafeMutex<T>,
+    //only None while dropping, the lock has to be released before interrupts are enabled again
+    guard: Option<spin::MutexGuard<'a, T>>,
+    interrupts_enabled: bool,
+}
+
+impl<T> Deref for IrqSafeMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for IrqSafeMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for IrqSafeMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(0, Ordering::Relaxed);
+        drop(self.guard.take());
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
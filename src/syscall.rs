@@ -0,0 +1,79 @@
+use crate::interrupts::InterruptContext;
+use crate::{io, process, thread, usermode};
+
+// Syscall numbers, passed in rax. Arguments go in rdi, rsi and rdx and the result is returned in rax.
+// rcx, rdx, rsi, rdi and r8 to r11 are zeroed, the other registers are preserved.
+
+/// write(buffer, length) - writes the bytes to serial and returns how many were written
+pub const SYS_WRITE: u64 = 0;
+/// exit(code) - ends the calling process with the exit code
+pub const SYS_EXIT: u64 = 1;
+/// sleep(ticks) - blocks the calling thread for the given number of timer ticks
+pub const SYS_SLEEP: u64 = 2;
+/// yield() - gives the rest of the time slice to another thread
+pub const SYS_YIELD: u64 = 3;
+/// getpid() - returns the PID of the calling process
+pub const SYS_GETPID: u64 = 4;
+
+/// Errors are returned as the negated value in rax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum SyscallError {
+    UnknownSyscall = 1,
+    BadAddress = 2,
+}
+
+impl SyscallError {
+    fn as_return_value(self) -> u64 {
+        (-(self as i64)) as u64
+    }
+}
+
+/// Called from the int 0x80 entry with interrupts disabled. Returns the context to resume, which is
+/// a different thread's when the syscall blocked or ended the caller.
+pub(crate) fn dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    let registers = unsafe { &mut *context };
+    let (rdi, rsi) = (registers.rdi, registers.rsi);
+    clear_scratch_registers(registers);
+
+    match registers.rax {
+        SYS_WRITE => registers.rax = sys_write(rdi, rsi).unwrap_or_else(|e| e.as_return_value()),
+        SYS_EXIT => {
+            process::mark_exited(rdi as i64);
+            return thread::schedule(context);
+        }
+        SYS_SLEEP => {
+            registers.rax = 0;
+            thread::mark_sleeping(rdi as usize);
+            return thread::schedule(context);
+        }
+        SYS_YIELD => {
+            registers.rax = 0;
+            return thread::schedule(context);
+        }
+        SYS_GETPID => registers.rax = process::current().map_or(0, |pid| pid.as_u64()),
+        _ => registers.rax = SyscallError::UnknownSyscall.as_return_value(),
+    }
+    context
+}
+
+//also before blocking, the caller's context is resumed from here later
+fn clear_scratch_registers(registers: &mut InterruptContext) {
+    registers.rcx = 0;
+    registers.rdx = 0;
+    registers.rsi = 0;
+    registers.rdi = 0;
+    registers.r8 = 0;
+    registers.r9 = 0;
+    registers.r10 = 0;
+    registers.r11 = 0;
+}
+
+fn sys_write(buffer: u64, length: u64) -> Result<u64, SyscallError> {
+    if !usermode::is_user_readable(buffer, length) {
+        return Err(SyscallError::BadAddress);
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(buffer as *const u8, length as usize) };
+    io::write_bytes(bytes);
+    Ok(length)
+}
@@ -0,0 +1,523 @@
+use crate::gdt;
+use crate::interrupts::InterruptContext;
+use crate::memory::AddressSpace;
+use crate::smp::{self, MAX_CPUS};
+use crate::sync::IrqSafeMutex;
+use crate::timer;
+use alloc::{boxed::Box, collections::BTreeMap, collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::arch::asm;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::segmentation::{Segment, CS, SS};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::VirtAddr;
+
+const STACK_SIZE: usize = 4096 * 16;
+//interrupts enabled + the reserved bit that is always set
+const INITIAL_RFLAGS: u64 = 0x202;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThreadState {
+    Running,
+    Ready,
+    Sleeping { until: usize },
+    //only runs again once somebody calls unblock
+    Blocked,
+    Dead,
+}
+
+struct Thread {
+    id: ThreadId,
+    state: ThreadState,
+    //set while a cpu runs on the thread's stack, from being picked until finish_switch after it was switched out
+    on_cpu: bool,
+    //where the registers of the thread were saved when it was switched out
+    context: *mut InterruptContext,
+    //None for the boot thread and the idle threads of the application processors, which run on stacks they already had
+    _stack: Option<Box<[u8]>>,
+    //top of the stack above, the cpu switches to it when the thread is interrupted in ring 3
+    kernel_stack_top: Option<VirtAddr>,
+    //None for threads that only need the kernel's page table
+    address_space: Option<Arc<AddressSpace>>,
+}
+
+//the raw context pointer only ever points into the thread's own stack
+unsafe impl Send for Thread {}
+
+impl Thread {
+    fn new(f: impl FnOnce() + Send + 'static) -> Self {
+        //boxed twice so thread_start gets a thin pointer
+        let entry: Box<Box<dyn FnOnce() + Send>> = Box::new(Box::new(f));
+        let stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+        let stack_top = (stack.as_ptr() as u64 + STACK_SIZE as u64) & !0xF;
+
+        //the thread starts by "returning" from the interrupt that never happened
+        let context_addr = stack_top - 16 - size_of::<InterruptContext>() as u64;
+        let context = context_addr as *mut InterruptContext;
+        unsafe {
+            context.write(InterruptContext {
+                rip: thread_start as *const () as u64,
+                cs: u64::from(CS::get_reg().0),
+                rflags: INITIAL_RFLAGS,
+                //entry expects to be called, i.e. rsp + 8 is 16 byte aligned
+                rsp: stack_top - 8,
+                ss: u64::from(SS::get_reg().0),
+                rdi: Box::into_raw(entry) as u64,
+                ..InterruptContext::default()
+            });
+        }
+
+        Self {
+            id: ThreadId::new(),
+            state: ThreadState::Ready,
+            on_cpu: false,
+            context,
+            _stack: Some(stack),
+            kernel_stack_top: Some(VirtAddr::new(stack_top)),
+            address_space: None,
+        }
+    }
+
+    //the code that is running right now, on the stack it already has
+    fn running_code() -> Self {
+        Self {
+            id: ThreadId::new(),
+            state: ThreadState::Running,
+            on_cpu: true,
+            context: core::ptr::null_mut(), //filled in on the first switch
+            _stack: None,
+            kernel_stack_top: None,
+            address_space: None,
+        }
+    }
+}
+
+//what the scheduler knows about one cpu
+#[derive(Clone, Copy)]
+struct CpuState {
+    current: ThreadId,
+    //runs when nothing else can, never in the ready queue
+    idle: ThreadId,
+    //the thread this cpu switched away from, until finish_switch ran on the new stack
+    previous: Option<ThreadId>,
+}
+
+struct Scheduler {
+    threads: BTreeMap<ThreadId, Thread>,
+    ready: VecDeque<ThreadId>,
+    cpus: [Option<CpuState>; MAX_CPUS],
+    kernel_page_table: PhysFrame,
+    //frees dead threads, blocked while there are none
+    reaper: Option<ThreadId>,
+    //(thread, waiter) - the waiter is unblocked once the reaper freed the thread
+    free_waiters: Vec<(ThreadId, ThreadId)>,
+}
+
+impl Scheduler {
+    fn new() -> Self {
+        let boot = Thread::running_code();
+        let idle = Thread::new(idle_loop);
+
+        let mut cpus = [None; MAX_CPUS];
+        cpus[0] = Some(CpuState {
+            current: boot.id,
+            idle: idle.id,
+            previous: None,
+        });
+        let mut threads = BTreeMap::new();
+        threads.insert(boot.id, boot);
+        threads.insert(idle.id, idle);
+        Self {
+            threads,
+            ready: VecDeque::new(),
+            cpus,
+            kernel_page_table: Cr3::read().0,
+            reaper: None,
+            free_waiters: Vec::new(),
+        }
+    }
+
+    fn cpu(&mut self, cpu: usize) -> &mut CpuState {
+        self.cpus[cpu]
+            .as_mut()
+            .expect("cpu is not known to the scheduler")
+    }
+
+    //Every thread is in the ready queue at most once, so with room for all of them the scheduler never has to grow
+    //it while switching. That happens with interrupts disabled, where the allocator must not be used.
+    fn add(&mut self, thread: Thread) {
+        let needed = self.threads.len() + 1;
+        if self.ready.capacity() < needed {
+            self.ready.reserve(needed - self.ready.len());
+        }
+        self.ready.push_back(thread.id);
+        self.threads.insert(thread.id, thread);
+    }
+
+    //threads that are still on a cpu are woken by a later switch, after finish_switch released them
+    fn wake_sleepers(&mut self) {
+        let now = timer::ticks();
+        for thread in self.threads.values_mut() {
+            if let ThreadState::Sleeping { until } = thread.state {
+                if now >= until && !thread.on_cpu {
+                    thread.state = ThreadState::Ready;
+                    self.ready.push_back(thread.id);
+                }
+            }
+        }
+    }
+
+    //A thread that is still on a cpu because it blocked itself right before yielding gets queued by finish_switch
+    fn unblock(&mut self, id: ThreadId) {
+        if let Some(thread) = self.threads.get_mut(&id) {
+            if thread.state == ThreadState::Blocked {
+                thread.state = ThreadState::Ready;
+                if !thread.on_cpu {
+                    self.ready.push_back(id);
+                }
+            }
+        }
+    }
+
+    fn wake_reaper(&mut self) {
+        if let Some(reaper) = self.reaper {
+            self.unblock(reaper);
+        }
+    }
+
+    //Dead threads can only be freed once no cpu is on their stack anymore. Frees their stacks, so it must not run
+    //inside the scheduler's interrupt entry.
+    fn reap_dead(&mut self) {
+        self.threads
+            .retain(|_, thread| thread.on_cpu || thread.state != ThreadState::Dead);
+
+        let threads = &self.threads;
+        let mut woken = Vec::new();
+        self.free_waiters.retain(|&(thread, waiter)| {
+            let freed = !threads.contains_key(&thread);
+            if freed {
+                woken.push(waiter);
+            }
+            !freed
+        });
+        for waiter in woken {
+            self.unblock(waiter);
+        }
+    }
+
+    fn block_current(&mut self, cpu: usize) {
+        let current = self.cpu(cpu).current;
+        self.threads
+            .get_mut(&current)
+            .expect("current thread missing")
+            .state = ThreadState::Blocked;
+    }
+
+    fn switch(&mut self, cpu: usize, context: *mut InterruptContext) -> *mut InterruptContext {
+        self.wake_sleepers();
+
+        let current_id = self.cpu(cpu).current;
+        let current = self
+            .threads
+            .get_mut(&current_id)
+            .expect("current thread missing");
+        current.context = context;
+        if current.state == ThreadState::Running {
+            current.state = ThreadState::Ready;
+        }
+
+        //round robin - the current thread goes to the back of the queue, the idle thread only runs when nothing else can
+        let next_id = match self.ready.pop_front() {
+            Some(id) => id,
+            None if current.state == ThreadState::Ready => current_id,
+            None => self.cpu(cpu).idle,
+        };
+        let kernel_page_table = self.kernel_page_table;
+        let next = self
+            .threads
+            .get_mut(&next_id)
+            .expect("ready thread missing");
+        next.state = ThreadState::Running;
+        next.on_cpu = true;
+        if let Some(stack_top) = next.kernel_stack_top {
+            gdt::set_kernel_stack(cpu, stack_top);
+        }
+        let page_table = next
+            .address_space
+            .as_ref()
+            .map_or(kernel_page_table, |space| space.level_4_frame());
+        let (active, cr3_flags) = Cr3::read();
+        if active != page_table {
+            //the kernel is mapped the same in every address space, so we can keep running on this stack
+            unsafe { Cr3::write(page_table, cr3_flags) };
+        }
+        let context = next.context;
+
+        let state = self.cpu(cpu);
+        if next_id != current_id {
+            state.previous = Some(current_id);
+        }
+        state.current = next_id;
+        context
+    }
+
+    //Runs on the new thread's stack, so the previous one can be queued again or freed without another cpu ending up on a stack that's still in use
+    fn finish_switch(&mut self, cpu: usize) {
+        let state = self.cpu(cpu);
+        let idle = state.idle;
+        let previous = match state.previous.take() {
+            Some(previous) => previous,
+            None => return,
+        };
+        let thread = self
+            .threads
+            .get_mut(&previous)
+            .expect("previous thread missing");
+        thread.on_cpu = false;
+        match thread.state {
+            ThreadState::Ready if previous != idle => self.ready.push_back(previous),
+            //nobody is on its stack anymore
+            ThreadState::Dead => self.wake_reaper(),
+            _ => {}
+        }
+    }
+}
+
+static SCHEDULER: IrqSafeMutex<Option<Scheduler>> = IrqSafeMutex::new("thread::SCHEDULER", None);
+
+/// Turns the code that is currently running into the boot thread and starts preemptive scheduling
+pub fn init() {
+    let mut scheduler = SCHEDULER.lock();
+    if scheduler.is_some() {
+        return;
+    }
+    let mut new = Scheduler::new();
+    let reaper = Thread::new(reaper_loop);
+    new.reaper = Some(reaper.id);
+    new.add(reaper);
+    *scheduler = Some(new);
+}
+
+/// Makes the code running on the application processor `cpu` its idle thread, so the cpu can be scheduled on.
+/// Called with interrupts disabled.
+pub(crate) fn init_ap(cpu: usize) {
+    let idle = Thread::running_code();
+    let mut scheduler = SCHEDULER.lock();
+    let scheduler = scheduler
+        .as_mut()
+        .expect("thread::init has not been called");
+    scheduler.cpus[cpu] = Some(CpuState {
+        current: idle.id,
+        idle: idle.id,
+        previous: None,
+    });
+    scheduler.threads.insert(idle.id, idle);
+}
+
+/// Called from the timer and yield interrupt entries with interrupts disabled.
+/// Returns the saved context of the thread that should run next.
+pub(crate) fn schedule(context: *mut InterruptContext) -> *mut InterruptContext {
+    let cpu = smp::current_cpu();
+    match SCHEDULER.lock().as_mut() {
+        Some(scheduler) => scheduler.switch(cpu, context),
+        None => context, //threads not initialized yet - keep running what was interrupted
+    }
+}
+
+/// Called by the interrupt entries right after they switched to the stack `schedule` returned
+pub(crate) extern "C" fn finish_switch() {
+    let cpu = smp::current_cpu();
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.finish_switch(cpu);
+    }
+}
+
+extern "C" fn thread_start(entry: *mut Box<dyn FnOnce() + Send>) -> ! {
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+    exit();
+}
+
+//Frees the stacks of dead threads outside of the interrupt entries. Blocks until finish_switch or kill wakes it,
+//reaping and blocking under the same lock so a thread dying in between isn't missed.
+fn reaper_loop() {
+    loop {
+        {
+            let mut scheduler = SCHEDULER.lock();
+            let scheduler = scheduler
+                .as_mut()
+                .expect("thread::init has not been called");
+            scheduler.reap_dead();
+            scheduler.block_current(smp::current_cpu());
+        }
+        yield_now();
+    }
+}
+
+fn idle_loop() {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Starts running `f` on a new kernel thread with its own stack
+pub fn spawn(f: impl FnOnce() + Send + 'static) -> ThreadId {
+    add(Thread::new(f))
+}
+
+/// Like spawn, but the thread runs with `address_space` active
+pub fn spawn_in(address_space: Arc<AddressSpace>, f: impl FnOnce() + Send + 'static) -> ThreadId {
+    let mut thread = Thread::new(f);
+    thread.address_space = Some(address_space);
+    add(thread)
+}
+
+fn add(thread: Thread) -> ThreadId {
+    let id = thread.id;
+    SCHEDULER
+        .lock()
+        .as_mut()
+        .expect("thread::init has not been called")
+        .add(thread);
+    id
+}
+
+pub fn current() -> ThreadId {
+    let mut scheduler = SCHEDULER.lock();
+    //interrupts are off while the lock is held, so the thread can't move to another cpu in between
+    let cpu = smp::current_cpu();
+    scheduler
+        .as_mut()
+        .expect("thread::init has not been called")
+        .cpu(cpu)
+        .current
+}
+
+/// Gives the rest of the time slice to the next ready thread
+pub fn yield_now() {
+    //has to match interrupts::YIELD_VECTOR
+    unsafe {
+        //other threads run before this returns and may change any memory, and the interrupt frame goes on this stack
+        asm!("int 0x81");
+    }
+}
+
+/// Blocks the current thread for at least `ticks` timer ticks
+pub fn sleep(ticks: usize) {
+    mark_sleeping(ticks);
+    yield_now();
+}
+
+/// Ends the current thread. Its stack is freed once another thread is running.
+pub fn exit() -> ! {
+    mark_dead();
+    yield_now();
+    unreachable!("dead thread was scheduled again");
+}
+
+/// Waits until the thread `id` has exited
+pub fn join(id: ThreadId) {
+    while is_alive(id) {
+        yield_now();
+    }
+}
+
+/// Blocks until the reaper freed the thread `id`, which drops everything it owned - e.g. its address space
+pub fn wait_freed(id: ThreadId) {
+    {
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = scheduler
+            .as_mut()
+            .expect("thread::init has not been called");
+        if !scheduler.threads.contains_key(&id) {
+            return;
+        }
+        //checked and blocked under the same lock, so the reaper can't free it in between
+        let cpu = smp::current_cpu();
+        let current = scheduler.cpu(cpu).current;
+        scheduler.free_waiters.push((id, current));
+        scheduler.block_current(cpu);
+    }
+    yield_now();
+}
+
+/// Ends the thread `id` the next time the scheduler runs.
+///
+/// The thread gets no chance to clean up, so it must not hold any locks - fine for threads running user programs, which only enter the kernel with interrupts disabled.
+pub(crate) fn kill(id: ThreadId) {
+    if id == current() {
+        exit();
+    }
+    let mut scheduler = SCHEDULER.lock();
+    let scheduler = scheduler
+        .as_mut()
+        .expect("thread::init has not been called");
+    if let Some(thread) = scheduler.threads.get_mut(&id) {
+        thread.state = ThreadState::Dead;
+        //threads that are still on a cpu wake the reaper from finish_switch
+        let reapable = !thread.on_cpu;
+        scheduler.ready.retain(|ready| *ready != id);
+        if reapable {
+            scheduler.wake_reaper();
+        }
+    }
+}
+
+/// Whether the scheduler still knows about `id`, dead threads are only cleaned up once no cpu runs on their stack
+pub fn exists(id: ThreadId) -> bool {
+    SCHEDULER
+        .lock()
+        .as_ref()
+        .expect("thread::init has not been called")
+        .threads
+        .contains_key(&id)
+}
+
+pub fn is_alive(id: ThreadId) -> bool {
+    SCHEDULER
+        .lock()
+        .as_ref()
+        .expect("thread::init has not been called")
+        .threads
+        .get(&id)
+        .map_or(false, |thread| thread.state != ThreadState::Dead)
+}
+
+//The mark_ functions only change the state, the switch happens on the next schedule - used by syscalls which are already inside the scheduler's interrupt entry
+
+pub(crate) fn mark_sleeping(ticks: usize) {
+    let until = timer::ticks() + ticks;
+    set_current_state(ThreadState::Sleeping { until });
+}
+
+pub(crate) fn mark_dead() {
+    set_current_state(ThreadState::Dead);
+}
+
+fn set_current_state(state: ThreadState) {
+    let mut scheduler = SCHEDULER.lock();
+    let scheduler = scheduler
+        .as_mut()
+        .expect("thread::init has not been called");
+    let current = scheduler.cpu(smp::current_cpu()).current;
+    scheduler
+        .threads
+        .get_mut(&current)
+        .expect("current thread missing")
+        .state = state;
+}
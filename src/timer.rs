@@ -1,16 +1,16 @@
+use crate::sync::IrqSafeMutex;
 use core::{
     pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
     task::{Context, Poll},
 };
 use futures_util::stream::{Stream, StreamExt};
 use futures_util::task::AtomicWaker;
-use lazy_static::lazy_static;
-use spin::Mutex;
 
-lazy_static! {
-    static ref TICKS: Mutex<TickCount> = Mutex::new(TickCount::new());
-}
+static TICKS: IrqSafeMutex<TickCount> = IrqSafeMutex::new("timer::TICKS", TickCount::new());
 static WAKER: AtomicWaker = AtomicWaker::new();
+//lock free copy of the tick count for code that can run while TICKS is locked (e.g. wakers called from increment)
+static TICK_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 pub struct TickCount {
     ticks: usize,
@@ -18,7 +18,7 @@ pub struct TickCount {
 }
 
 impl TickCount {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             ticks: 0,
             new_tick: false,
@@ -64,9 +64,15 @@ impl Stream for TickStream {
 }
 
 pub fn tick() {
+    TICK_COUNT.fetch_add(1, Ordering::Relaxed);
     TICKS.lock().increment();
 }
 
+/// Number of timer interrupts since boot
+pub fn ticks() -> usize {
+    TICK_COUNT.load(Ordering::Relaxed)
+}
+
 pub async fn sleep(ticks: usize) {
     let mut tick_stream = TickStream::new();
     for _ in 0..ticks {
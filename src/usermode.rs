@@ -0,0 +1,207 @@
+use crate::gdt;
+use crate::memory::{self, AddressSpace};
+use core::arch::asm;
+use x86_64::structures::paging::{
+    mapper::{MapToError, TranslateResult},
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+/// Addresses user programs may use. Picked so the range doesn't share a level 4 entry with
+/// anything the bootloader or the kernel heap mapped, otherwise the USER_ACCESSIBLE bit would be missing on the parent tables.
+pub const USER_SPACE_START: u64 = 0x0000_5000_0000_0000;
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Where loaded programs get their stack
+pub const USER_STACK_TOP: u64 = USER_SPACE_END - 0x1000;
+pub const USER_STACK_SIZE: u64 = 4096 * 16;
+
+//interrupts enabled + the reserved bit that is always set
+const USER_RFLAGS: u64 = 0x202;
+
+/// Maps zeroed frames for `[start, start + size)` into the active address space, accessible from ring 3.
+pub fn map_user_region(
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = unsafe { memory::active_mapper() };
+    map_region(&mut mapper, start, size, flags)
+}
+
+/// Like map_user_region, but for an address space that doesn't have to be active
+pub fn map_user_region_in(
+    space: &mut AddressSpace,
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = unsafe { space.mapper() };
+    map_region(&mut mapper, start, size, flags)
+}
+
+//Pages that are already mapped keep their frame and get the combined permissions - ELF segments may share a page
+fn map_region(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    assert!(
+        is_user_range(start.as_u64(), size),
+        "region is outside of user space"
+    );
+    if size == 0 {
+        return Ok(());
+    }
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    //the parent tables must allow everything, the last level decides
+    let table_flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let first = Page::<Size4KiB>::containing_address(start);
+    let last = Page::<Size4KiB>::containing_address(start + size - 1u64);
+
+    memory::with_frame_allocator(|frame_allocator| {
+        for page in Page::range_inclusive(first, last) {
+            if let TranslateResult::Mapped { flags: old, .. } =
+                mapper.translate(page.start_address())
+            {
+                let mut merged = old | flags;
+                if !(old.contains(PageTableFlags::NO_EXECUTE)
+                    && flags.contains(PageTableFlags::NO_EXECUTE))
+                {
+                    merged.remove(PageTableFlags::NO_EXECUTE);
+                }
+                unsafe {
+                    mapper
+                        .update_flags(page, merged)
+                        .expect("page was mapped a moment ago")
+                        .flush();
+                }
+                continue;
+            }
+
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe {
+                let frame_ptr: *mut u8 = (memory::physical_memory_offset()
+                    + frame.start_address().as_u64())
+                .as_mut_ptr();
+                frame_ptr.write_bytes(0, Page::<Size4KiB>::SIZE as usize);
+                mapper
+                    .map_to_with_table_flags(page, frame, flags, table_flags, frame_allocator)?
+                    .flush();
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Copies `data` to `addr` in `space`. The pages have to be mapped already.
+pub fn write_in(space: &mut AddressSpace, addr: VirtAddr, data: &[u8]) {
+    let mut written = 0;
+    for_each_chunk(space, addr, data.len() as u64, |chunk| {
+        chunk.copy_from_slice(&data[written..written + chunk.len()]);
+        written += chunk.len();
+    });
+}
+
+/// Zeroes `[addr, addr + len)` in `space`. The pages have to be mapped already.
+pub fn zero_in(space: &mut AddressSpace, addr: VirtAddr, len: u64) {
+    for_each_chunk(space, addr, len, |chunk| chunk.fill(0));
+}
+
+//Goes through the range page by page using the physical memory mapping, so the address space doesn't need to be active
+fn for_each_chunk(
+    space: &mut AddressSpace,
+    addr: VirtAddr,
+    len: u64,
+    mut f: impl FnMut(&mut [u8]),
+) {
+    //the lock keeps the page tables from changing underneath us
+    memory::with_frame_allocator(|_| {
+        let mapper = unsafe { space.mapper() };
+        let end = addr + len;
+        let mut current = addr;
+        while current < end {
+            let page_end = current.align_down(Page::<Size4KiB>::SIZE) + Page::<Size4KiB>::SIZE;
+            let chunk_len = (end.min(page_end) - current) as usize;
+            let phys = mapper
+                .translate_addr(current)
+                .expect("writing to an unmapped user page");
+            let chunk = unsafe {
+                core::slice::from_raw_parts_mut(
+                    (memory::physical_memory_offset() + phys.as_u64()).as_mut_ptr::<u8>(),
+                    chunk_len,
+                )
+            };
+            f(chunk);
+            current += chunk_len as u64;
+        }
+    });
+}
+
+/// Whether `[addr, addr + len)` lies completely inside user space
+pub fn is_user_range(addr: u64, len: u64) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => addr >= USER_SPACE_START && end <= USER_SPACE_END,
+        None => false,
+    }
+}
+
+/// Whether ring 3 code may read `[addr, addr + len)`, i.e. every page of it is mapped as user accessible
+pub fn is_user_readable(addr: u64, len: u64) -> bool {
+    if !is_user_range(addr, len) {
+        return false;
+    }
+    if len == 0 {
+        return true;
+    }
+    let mapper = unsafe { memory::active_mapper() };
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+    let last = Page::<Size4KiB>::containing_address(VirtAddr::new(addr + len - 1));
+    Page::range_inclusive(first, last).all(|page| match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { flags, .. } => flags.contains(PageTableFlags::USER_ACCESSIBLE),
+        _ => false,
+    })
+}
+
+/// Drops the current thread to ring 3 and jumps to `entry` with the given stack.
+///
+/// The kernel stack of the thread is reused for interrupts and syscalls from then on, so nothing on it may be needed anymore.
+pub fn enter(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    let (code_selector, data_selector) = gdt::user_selectors();
+    unsafe {
+        //build the frame iretq expects: ss, rsp, rflags, cs, rip, then clear every register so no kernel value reaches ring 3
+        asm!(
+            "push {ss}",
+            "push {rsp}",
+            "push {rflags}",
+            "push {cs}",
+            "push {rip}",
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+            ss = in(reg) u64::from(data_selector.0),
+            rsp = in(reg) stack_top.as_u64(),
+            rflags = in(reg) USER_RFLAGS,
+            cs = in(reg) u64::from(code_selector.0),
+            rip = in(reg) entry.as_u64(),
+            options(noreturn)
+        );
+    }
+}
@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::graphics::{CursorImage, CursorMode, Vga, VGA};
+use finn_os::io::MOUSE;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+//the vga frame buffer, mapped at the same address
+const FRAME_BUFFER: *const u8 = 0xA0000 as *const u8;
+
+fn back_buffer_pixel(vga: &Vga, x: usize, y: usize) -> u8 {
+    unsafe { vga.get_buffer().add(y * 320 + x).read_volatile() }
+}
+
+fn screen_pixel(x: usize, y: usize) -> u8 {
+    unsafe { FRAME_BUFFER.add(y * 320 + x).read_volatile() }
+}
+
+//the hotspot is black, the pixel right of it is masked out and the one below that is white
+fn assert_arrow_at(x: usize, y: usize, background: u8) {
+    assert_eq!(screen_pixel(x, y), 0x0);
+    assert_eq!(screen_pixel(x + 1, y), background);
+    assert_eq!(screen_pixel(x + 1, y + 2), 0xF);
+}
+
+fn assert_back_buffer_is(vga: &Vga, x: usize, y: usize, color: u8) {
+    for (dx, dy) in [(0, 0), (1, 0), (1, 2)] {
+        assert_eq!(back_buffer_pixel(vga, x + dx, y + dy), color);
+    }
+}
+
+#[test_case]
+fn test_arrow_mask() {
+    let arrow = CursorImage::ARROW;
+    assert_eq!(arrow.pixel(0, 0), Some(0x0));
+    assert_eq!(arrow.pixel(1, 0), None);
+    assert_eq!(arrow.pixel(1, 2), Some(0xF));
+    assert_eq!(arrow.pixel(9, 9), Some(0x0));
+    //outside of the image
+    assert_eq!(arrow.pixel(10, 0), None);
+    assert_eq!(arrow.pixel(0, 16), None);
+}
+
+#[test_case]
+fn test_cursor_follows_mouse() {
+    MOUSE.lock().set_position(50, 60);
+    let mut vga = VGA.lock();
+    vga.clear_screen(0x3);
+    vga.swap_buffers();
+    assert_eq!(vga.cursor_position(), (50, 60));
+    assert_arrow_at(50, 60, 0x3);
+}
+
+#[test_case]
+fn test_composited_cursor() {
+    MOUSE.lock().set_position(100, 100);
+    let mut vga = VGA.lock();
+    vga.set_cursor_mode(CursorMode::Composited);
+    vga.clear_screen(0x3);
+    vga.swap_buffers();
+    assert_arrow_at(100, 100, 0x3);
+    //put back after the copy
+    assert_back_buffer_is(&vga, 100, 100, 0x3);
+    vga.clear_screen(0x0);
+    vga.swap_buffers();
+}
+
+#[test_case]
+fn test_overlay_cursor() {
+    MOUSE.lock().set_position(100, 100);
+    let mut vga = VGA.lock();
+    vga.set_cursor_mode(CursorMode::Overlay);
+    vga.clear_screen(0x3);
+    vga.swap_buffers();
+    assert_arrow_at(100, 100, 0x3);
+    assert_back_buffer_is(&vga, 100, 100, 0x3);
+    //moves without a new frame, the old spot gets the frame back
+    MOUSE.lock().set_position(150, 120);
+    vga.update_cursor();
+    assert_arrow_at(150, 120, 0x3);
+    assert_eq!(screen_pixel(100, 100), 0x3);
+    assert_eq!(screen_pixel(101, 102), 0x3);
+    assert_back_buffer_is(&vga, 150, 120, 0x3);
+    //leaving the mode takes it off the screen
+    vga.set_cursor_mode(CursorMode::Hidden);
+    assert_eq!(screen_pixel(150, 120), 0x3);
+    vga.swap_buffers();
+    assert_eq!(screen_pixel(150, 120), 0x3);
+    vga.set_cursor_mode(CursorMode::Composited);
+    vga.clear_screen(0x0);
+    vga.swap_buffers();
+}
@@ -0,0 +1,140 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::elf::{self, Elf, ElfError, HELLO};
+use finn_os::io::{start_capture, stop_capture};
+use finn_os::process::{self, ExitStatus};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+//offsets into HELLO, see `readelf -lh programs/hello`
+const PROGRAM_HEADERS: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+fn patched(offset: usize, bytes: &[u8]) -> Vec<u8> {
+    let mut data = HELLO.to_vec();
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    data
+}
+
+fn parse_error(data: &[u8]) -> Option<ElfError> {
+    Elf::parse(data).err()
+}
+
+#[test_case]
+fn test_spawn_with_arguments() {
+    start_capture();
+    let pid = process::spawn(HELLO, &["hello", "world"]).expect("spawn failed");
+    assert_eq!(process::wait(pid), Some(ExitStatus::Exited(2)));
+    assert_eq!(stop_capture(), "hello from elf\nhello\nworld\n");
+}
+
+#[test_case]
+fn test_parse_valid() {
+    let elf = Elf::parse(HELLO).expect("parse failed");
+    assert_eq!(elf.entry().as_u64(), 0x5000_0000_1000);
+    assert_eq!(
+        elf.program_headers()
+            .filter(|header| header.kind == elf::PT_LOAD)
+            .count(),
+        4
+    );
+}
+
+#[test_case]
+fn test_reject_malformed_header() {
+    assert_eq!(parse_error(&HELLO[..32]), Some(ElfError::TooShort));
+    assert_eq!(
+        parse_error(&patched(0, b"\x7fELG")),
+        Some(ElfError::BadMagic)
+    );
+    assert_eq!(parse_error(&patched(4, &[1])), Some(ElfError::Not64Bit));
+    assert_eq!(
+        parse_error(&patched(5, &[2])),
+        Some(ElfError::NotLittleEndian)
+    );
+    assert_eq!(parse_error(&patched(6, &[0])), Some(ElfError::BadVersion));
+    //ET_DYN
+    assert_eq!(
+        parse_error(&patched(16, &[3, 0])),
+        Some(ElfError::NotExecutable)
+    );
+    //EM_386
+    assert_eq!(
+        parse_error(&patched(18, &[3, 0])),
+        Some(ElfError::WrongMachine)
+    );
+    //entry point in the read only first segment
+    let entry = 0x5000_0000_0000u64.to_le_bytes();
+    assert_eq!(
+        parse_error(&patched(24, &entry)),
+        Some(ElfError::EntryNotExecutable)
+    );
+}
+
+#[test_case]
+fn test_reject_malformed_program_headers() {
+    //program header table starts past the end of the file
+    let offset = (HELLO.len() as u64).to_le_bytes();
+    assert_eq!(
+        parse_error(&patched(32, &offset)),
+        Some(ElfError::BadProgramHeaders)
+    );
+    assert_eq!(
+        parse_error(&patched(56, &[0xff, 0xff])),
+        Some(ElfError::BadProgramHeaders)
+    );
+    assert_eq!(
+        parse_error(&patched(54, &[16, 0])),
+        Some(ElfError::BadProgramHeaders)
+    );
+
+    let code = PROGRAM_HEADERS + PROGRAM_HEADER_SIZE;
+    let huge = 0x10_0000u64.to_le_bytes();
+    let mut data = patched(code + 32, &huge);
+    data[code + 40..code + 48].copy_from_slice(&huge);
+    assert_eq!(parse_error(&data), Some(ElfError::SegmentOutOfBounds));
+
+    let kernel = 0x1000u64.to_le_bytes();
+    assert_eq!(
+        parse_error(&patched(code + 16, &kernel)),
+        Some(ElfError::SegmentOutsideUserSpace)
+    );
+
+    //.data + .bss with a memory size smaller than the file size
+    let data_segment = PROGRAM_HEADERS + 3 * PROGRAM_HEADER_SIZE;
+    let one = 1u64.to_le_bytes();
+    assert_eq!(
+        parse_error(&patched(data_segment + 40, &one)),
+        Some(ElfError::BadSegmentSize)
+    );
+}
+
+#[test_case]
+fn test_reject_too_many_arguments() {
+    let args = [core::str::from_utf8(&[b'a'; 4096]).unwrap(); 16];
+    assert_eq!(
+        process::spawn(HELLO, &args).err(),
+        Some(ElfError::ArgumentsTooLarge)
+    );
+}
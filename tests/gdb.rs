@@ -0,0 +1,221 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::gdb::{self, Connection, Trap, TRAP_FLAG};
+use finn_os::interrupts::InterruptContext;
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+//plays the debugger's side from a prepared list of bytes
+struct Script {
+    input: VecDeque<u8>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Connection for Script {
+    fn read_byte(&mut self) -> u8 {
+        self.input.pop_front().expect("debugger script ran out")
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.output.lock().push(byte);
+    }
+}
+
+fn packet(data: &str) -> String {
+    let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    format!("${}#{:02x}", data, sum)
+}
+
+//one stop: acknowledges the stop reply, then sends `commands` and acknowledges their replies - the last one has to resume
+fn stop(commands: &[&str]) -> String {
+    let mut script = String::from("+");
+    for command in commands {
+        script.push_str(&packet(command));
+        if !command.starts_with(['c', 's', 'k']) {
+            script.push('+');
+        }
+    }
+    script
+}
+
+fn attach(script: &str) -> Arc<Mutex<Vec<u8>>> {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    gdb::attach(Box::new(Script {
+        input: script.bytes().collect(),
+        output: output.clone(),
+    }));
+    output
+}
+
+//the data of every packet the stub sent
+fn replies(output: &Mutex<Vec<u8>>) -> Vec<String> {
+    let output = String::from_utf8(output.lock().clone()).unwrap();
+    output
+        .split('$')
+        .skip(1)
+        .map(|packet| String::from(packet.split('#').next().unwrap()))
+        .collect()
+}
+
+fn le_hex(value: u64) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[test_case]
+fn test_registers() {
+    let output = attach(&stop(&["g", "P0=efbeadde00000000", "p0", "p10", "c"]));
+    let mut context = InterruptContext {
+        rax: 0x1122_3344_5566_7788,
+        rip: 0x1000,
+        rflags: 0x202,
+        ..Default::default()
+    };
+    assert!(gdb::handle_trap(&mut context, Trap::Breakpoint));
+    gdb::detach();
+
+    let replies = replies(&output);
+    assert_eq!(replies[0], "T05");
+    //17 64 bit registers, then eflags and 6 segment registers with 32 bits
+    assert_eq!(replies[1].len(), 17 * 16 + 7 * 8);
+    assert!(replies[1].starts_with(&le_hex(0x1122_3344_5566_7788)));
+    assert_eq!(replies[2], "OK");
+    assert_eq!(replies[3], le_hex(0xdead_beef));
+    assert_eq!(replies[4], le_hex(0x1000));
+    assert_eq!(context.rax, 0xdead_beef);
+}
+
+#[test_case]
+fn test_step_and_continue_flags() {
+    let output = attach(&(stop(&["s2000"]) + &stop(&["c"])));
+    let mut context = InterruptContext {
+        rip: 0x1000,
+        rflags: 0x202,
+        ..Default::default()
+    };
+    gdb::handle_trap(&mut context, Trap::Breakpoint);
+    assert_eq!(context.rip, 0x2000);
+    assert_ne!(context.rflags & TRAP_FLAG, 0);
+    //the step trap clears the flag again
+    gdb::handle_trap(&mut context, Trap::Step);
+    assert_eq!(context.rflags & TRAP_FLAG, 0);
+    gdb::detach();
+    assert_eq!(replies(&output), ["T05", "T05"]);
+}
+
+#[test_case]
+fn test_memory() {
+    static mut DATA: [u8; 4] = [1, 2, 3, 4];
+    let address = core::ptr::addr_of!(DATA) as u64;
+    let output = attach(&stop(&[
+        &format!("m{:x},4", address),
+        &format!("M{:x},2:aabb", address),
+        "m0,1",
+        "c",
+    ]));
+    gdb::handle_trap(&mut InterruptContext::default(), Trap::Breakpoint);
+    gdb::detach();
+
+    assert_eq!(replies(&output), ["T05", "01020304", "OK", "E14"]);
+    assert_eq!(
+        unsafe { core::ptr::addr_of!(DATA).read_volatile() },
+        [0xaa, 0xbb, 3, 4]
+    );
+}
+
+#[test_case]
+fn test_packet_handling() {
+    //a corrupted packet is asked for again, unknown packets get an empty reply
+    let script = format!(
+        "+$g#00{}+{}+{}+{}",
+        packet("qSupported:swbreak+"),
+        packet("vMustReplyEmpty"),
+        packet("?"),
+        packet("c")
+    );
+    let output = attach(&script);
+    gdb::handle_trap(&mut InterruptContext::default(), Trap::Breakpoint);
+    gdb::detach();
+
+    assert_eq!(output.lock()[..7], *b"$T05#b9");
+    assert_eq!(output.lock()[7], b'-');
+    assert_eq!(
+        replies(&output),
+        ["T05", "PacketSize=1000;swbreak+", "", "S05"]
+    );
+}
+
+#[inline(never)]
+fn triple(x: u64) -> u64 {
+    x * 3
+}
+
+#[test_case]
+fn test_software_breakpoint() {
+    let address = triple as *const () as u64;
+    let original = unsafe { *(address as *const u8) };
+    let output = attach(
+        &(stop(&[&format!("Z0,{:x},1", address), "c"])
+            + &stop(&["p10", &format!("z0,{:x},1", address), "c"])),
+    );
+    gdb::breakpoint();
+    assert_eq!(unsafe { *(address as *const u8) }, 0xCC);
+    assert_eq!(core::hint::black_box(triple)(5), 15);
+    gdb::detach();
+
+    assert_eq!(unsafe { *(address as *const u8) }, original);
+    assert_eq!(
+        replies(&output),
+        ["T05", "OK", "T05swbreak:;", &le_hex(address), "OK"]
+    );
+}
+
+#[test_case]
+fn test_single_step() {
+    let output = attach(&(stop(&["p10", "s"]) + &stop(&["p10", "c"])));
+    gdb::breakpoint();
+    gdb::detach();
+
+    let replies = replies(&output);
+    assert_eq!(replies.len(), 4);
+    assert_eq!(replies[0], "T05");
+    assert_eq!(replies[2], "T05");
+    //one instruction further
+    assert_ne!(replies[1], replies[3]);
+}
+
+#[test_case]
+fn test_detach() {
+    let output = attach(&stop(&["D"]));
+    gdb::breakpoint();
+    assert!(!gdb::is_attached());
+    assert_eq!(replies(&output), ["T05", "OK"]);
+    //without a debugger breakpoints are just logged
+    gdb::breakpoint();
+}
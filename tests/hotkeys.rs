@@ -0,0 +1,159 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use finn_os::executor::{Executor, Task};
+use finn_os::io::{
+    add_scancode, pressed_keys, register_hotkey, unregister_hotkey, Hotkey, HotkeyAction, KeyCode,
+    KeyEvent, KeyState, KeyboardEvent, KeyboardStream,
+};
+use futures_util::future::FutureExt;
+use futures_util::stream::StreamExt;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn count() {
+    CALLS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn count_twice() {
+    CALLS.fetch_add(2, Ordering::SeqCst);
+}
+
+fn next(stream: &mut KeyboardStream) -> Option<KeyboardEvent> {
+    stream.next().now_or_never().flatten()
+}
+
+//ctrl + h, pressed and released
+fn press_ctrl_h() {
+    for scancode in [0x1D, 0x23, 0xA3, 0x9D] {
+        add_scancode(scancode);
+    }
+}
+
+#[test_case]
+fn test_callback_comes_before_streams() {
+    let hotkey = Hotkey::new(KeyCode::H).ctrl();
+    register_hotkey(hotkey, HotkeyAction::Callback(count));
+    let mut stream = KeyboardStream::new();
+    CALLS.store(0, Ordering::SeqCst);
+    //ctrl + h, h held down
+    for scancode in [0x1D, 0x23, 0x23] {
+        add_scancode(scancode);
+    }
+    assert!(!pressed_keys().contains(KeyCode::H));
+    for scancode in [0xA3, 0x9D] {
+        add_scancode(scancode);
+    }
+    //neither the press nor the release of h gets through
+    let ctrl_down = next(&mut stream).unwrap();
+    assert_eq!(ctrl_down.event.code, KeyCode::ControlLeft);
+    assert_eq!(ctrl_down.event.state, KeyState::Down);
+    let ctrl_up = next(&mut stream).unwrap();
+    assert_eq!(ctrl_up.event.code, KeyCode::ControlLeft);
+    assert_eq!(ctrl_up.event.state, KeyState::Up);
+    assert!(next(&mut stream).is_none());
+    //the hardware repeat doesn't trigger it again
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    assert!(unregister_hotkey(hotkey));
+}
+
+#[test_case]
+fn test_modifiers_have_to_match_exactly() {
+    let hotkey = Hotkey::new(KeyCode::H).ctrl();
+    register_hotkey(hotkey, HotkeyAction::Callback(count));
+    let mut stream = KeyboardStream::new();
+    CALLS.store(0, Ordering::SeqCst);
+    //shift + ctrl + h and plain h
+    for scancode in [0x2A, 0x1D, 0x23, 0xA3, 0x9D, 0xAA, 0x23, 0xA3] {
+        add_scancode(scancode);
+    }
+    assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+    let mut presses = 0;
+    while let Some(event) = next(&mut stream) {
+        if event.event == KeyEvent::new(KeyCode::H, KeyState::Down) {
+            presses += 1;
+        }
+    }
+    assert_eq!(presses, 2);
+    assert!(unregister_hotkey(hotkey));
+}
+
+#[test_case]
+fn test_register_replaces_and_unregister_removes() {
+    let hotkey = Hotkey::new(KeyCode::H).ctrl();
+    register_hotkey(hotkey, HotkeyAction::Callback(count));
+    register_hotkey(hotkey, HotkeyAction::Callback(count_twice));
+    CALLS.store(0, Ordering::SeqCst);
+    press_ctrl_h();
+    pressed_keys();
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    assert!(unregister_hotkey(hotkey));
+    assert!(!unregister_hotkey(hotkey));
+    press_ctrl_h();
+    pressed_keys();
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+}
+
+//runs after the keyboard state was unlocked, so it can look at it
+fn count_if_ctrl_held() {
+    if pressed_keys().contains(KeyCode::ControlLeft) {
+        count();
+    }
+}
+
+#[test_case]
+fn test_callback_can_use_the_keyboard() {
+    let hotkey = Hotkey::new(KeyCode::H).ctrl();
+    register_hotkey(hotkey, HotkeyAction::Callback(count_if_ctrl_held));
+    CALLS.store(0, Ordering::SeqCst);
+    //ctrl + h, ctrl still held
+    for scancode in [0x1D, 0x23, 0xA3] {
+        add_scancode(scancode);
+    }
+    pressed_keys();
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    add_scancode(0x9D);
+    pressed_keys();
+    assert!(unregister_hotkey(hotkey));
+}
+
+fn counting_task() -> Task {
+    Task::new(async { count() })
+}
+
+#[test_case]
+fn test_task_action() {
+    let hotkey = Hotkey::new(KeyCode::F5).alt();
+    register_hotkey(hotkey, HotkeyAction::Task(counting_task));
+    let mut executor = Executor::new();
+    CALLS.store(0, Ordering::SeqCst);
+    for scancode in [0x38, 0x3F, 0xBF, 0xB8] {
+        add_scancode(scancode);
+    }
+    pressed_keys();
+    //spawned, but it only runs on the executor
+    assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+    executor.test_run();
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    assert!(unregister_hotkey(hotkey));
+}
@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::sync::IrqSafeMutex;
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+static LOCK: IrqSafeMutex<u32> = IrqSafeMutex::new("LOCK", 0);
+static OTHER: IrqSafeMutex<u32> = IrqSafeMutex::new("OTHER", 0);
+
+#[test_case]
+fn test_interrupts_disabled_while_locked() {
+    assert!(interrupts::are_enabled());
+    {
+        let mut value = LOCK.lock();
+        *value += 1;
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*LOCK.lock(), 1);
+}
+
+#[test_case]
+fn test_interrupts_stay_disabled() {
+    interrupts::without_interrupts(|| {
+        drop(LOCK.lock());
+        assert!(!interrupts::are_enabled());
+    });
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn test_nested_locks() {
+    {
+        let _lock = LOCK.lock();
+        {
+            let _other = OTHER.lock();
+            assert!(!interrupts::are_enabled());
+        }
+        //the outer guard still holds its lock
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn test_try_lock() {
+    let guard = LOCK.try_lock().expect("lock is free");
+    assert!(LOCK.is_locked());
+    assert!(LOCK.try_lock().is_none());
+    drop(guard);
+    assert!(!LOCK.is_locked());
+    assert!(interrupts::are_enabled());
+}
@@ -0,0 +1,237 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::io::{
+    reset_keyboard, set_leds, set_scancode_set, set_typematic, KeyCode, KeyEvent, KeyState,
+    Keyboard, Leds, RepeatDelay, ScancodeDecoder, ScancodeSet, CONTROLLER,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+//feeds all bytes, only the last one may complete the sequence
+fn decode(decoder: &mut ScancodeDecoder, bytes: &[u8]) -> Option<KeyEvent> {
+    let (last, prefix) = bytes.split_last().unwrap();
+    for byte in prefix {
+        assert_eq!(decoder.add_byte(*byte), Ok(None));
+    }
+    decoder.add_byte(*last).unwrap()
+}
+
+fn check_extended(code: u8, key: KeyCode) {
+    let mut decoder = ScancodeDecoder::new();
+    assert_eq!(
+        decode(&mut decoder, &[0xE0, code]),
+        Some(KeyEvent::new(key, KeyState::Down))
+    );
+    //release is the same code with the top bit set
+    assert_eq!(
+        decode(&mut decoder, &[0xE0, code | 0x80]),
+        Some(KeyEvent::new(key, KeyState::Up))
+    );
+}
+
+#[test_case]
+fn test_numpad_enter() {
+    check_extended(0x1C, KeyCode::NumpadEnter);
+}
+
+#[test_case]
+fn test_control_right() {
+    check_extended(0x1D, KeyCode::ControlRight);
+}
+
+#[test_case]
+fn test_numpad_slash() {
+    check_extended(0x35, KeyCode::NumpadSlash);
+}
+
+#[test_case]
+fn test_alt_right() {
+    check_extended(0x38, KeyCode::AltRight);
+}
+
+#[test_case]
+fn test_break() {
+    check_extended(0x46, KeyCode::Break);
+}
+
+#[test_case]
+fn test_home() {
+    check_extended(0x47, KeyCode::Home);
+}
+
+#[test_case]
+fn test_arrow_up() {
+    check_extended(0x48, KeyCode::ArrowUp);
+}
+
+#[test_case]
+fn test_page_up() {
+    check_extended(0x49, KeyCode::PageUp);
+}
+
+#[test_case]
+fn test_arrow_left() {
+    check_extended(0x4B, KeyCode::ArrowLeft);
+}
+
+#[test_case]
+fn test_arrow_right() {
+    check_extended(0x4D, KeyCode::ArrowRight);
+}
+
+#[test_case]
+fn test_end() {
+    check_extended(0x4F, KeyCode::End);
+}
+
+#[test_case]
+fn test_arrow_down() {
+    check_extended(0x50, KeyCode::ArrowDown);
+}
+
+#[test_case]
+fn test_page_down() {
+    check_extended(0x51, KeyCode::PageDown);
+}
+
+#[test_case]
+fn test_insert() {
+    check_extended(0x52, KeyCode::Insert);
+}
+
+#[test_case]
+fn test_delete() {
+    check_extended(0x53, KeyCode::Delete);
+}
+
+#[test_case]
+fn test_windows_left() {
+    check_extended(0x5B, KeyCode::WindowsLeft);
+}
+
+#[test_case]
+fn test_windows_right() {
+    check_extended(0x5C, KeyCode::WindowsRight);
+}
+
+#[test_case]
+fn test_menus() {
+    check_extended(0x5D, KeyCode::Menus);
+}
+
+#[test_case]
+fn test_print_screen() {
+    let mut decoder = ScancodeDecoder::new();
+    //the fake left shift around it doesn't produce events
+    assert_eq!(
+        decode(&mut decoder, &[0xE0, 0x2A, 0xE0, 0x37]),
+        Some(KeyEvent::new(KeyCode::PrintScreen, KeyState::Down))
+    );
+    assert_eq!(
+        decode(&mut decoder, &[0xE0, 0xB7]),
+        Some(KeyEvent::new(KeyCode::PrintScreen, KeyState::Up))
+    );
+    assert_eq!(decode(&mut decoder, &[0xE0, 0xAA]), None);
+}
+
+#[test_case]
+fn test_pause() {
+    let mut decoder = ScancodeDecoder::new();
+    assert_eq!(
+        decode(&mut decoder, &[0xE1, 0x1D, 0x45]),
+        Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Down))
+    );
+    assert_eq!(
+        decode(&mut decoder, &[0xE1, 0x9D, 0xC5]),
+        Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Up))
+    );
+}
+
+#[test_case]
+fn test_extended_keys_dont_collide_with_numpad() {
+    let mut decoder = ScancodeDecoder::new();
+    assert_eq!(
+        decoder.add_byte(0x48),
+        Ok(Some(KeyEvent::new(KeyCode::Numpad8, KeyState::Down)))
+    );
+    assert_eq!(
+        decode(&mut decoder, &[0xE0, 0x48]),
+        Some(KeyEvent::new(KeyCode::ArrowUp, KeyState::Down))
+    );
+    assert_eq!(
+        decoder.add_byte(0x9D),
+        Ok(Some(KeyEvent::new(KeyCode::ControlLeft, KeyState::Up)))
+    );
+}
+
+#[test_case]
+fn test_invalid_sequence_resets() {
+    let mut decoder = ScancodeDecoder::new();
+    assert_eq!(decoder.add_byte(0xE0), Ok(None));
+    assert_eq!(decoder.add_byte(0x10), Err(()));
+    assert_eq!(decoder.add_byte(0xE1), Ok(None));
+    assert_eq!(decoder.add_byte(0x45), Err(()));
+    assert_eq!(
+        decoder.add_byte(0x1E),
+        Ok(Some(KeyEvent::new(KeyCode::A, KeyState::Down)))
+    );
+}
+
+#[test_case]
+fn test_reset() {
+    assert_eq!(reset_keyboard(), Ok(()));
+}
+
+#[test_case]
+fn test_scancode_set_translation() {
+    assert_eq!(set_scancode_set(ScancodeSet::Set1), Ok(()));
+    assert_eq!(CONTROLLER.lock().config().unwrap() & 0x40, 0);
+    assert_eq!(set_scancode_set(ScancodeSet::Set2), Ok(()));
+    assert_eq!(CONTROLLER.lock().config().unwrap() & 0x40, 0x40);
+}
+
+#[test_case]
+fn test_typematic() {
+    assert_eq!(set_typematic(RepeatDelay::Ms250, 0), Ok(()));
+    assert_eq!(set_typematic(RepeatDelay::Ms500, 0x0B), Ok(()));
+}
+
+#[test_case]
+fn test_leds_follow_lock_keys() {
+    let mut keyboard = Keyboard::new();
+    assert!(keyboard
+        .process_key_ev(KeyEvent::new(KeyCode::CapsLock, KeyState::Down))
+        .is_none());
+    keyboard.process_key_ev(KeyEvent::new(KeyCode::NumpadLock, KeyState::Down));
+    assert_eq!(keyboard.leds(), Leds::CAPS_LOCK | Leds::NUM_LOCK);
+    assert_eq!(
+        keyboard.take_led_update(),
+        Some(Leds::CAPS_LOCK | Leds::NUM_LOCK)
+    );
+    assert_eq!(keyboard.take_led_update(), None);
+    //other keys leave the LEDs alone
+    keyboard.process_key_ev(KeyEvent::new(KeyCode::A, KeyState::Down));
+    assert_eq!(keyboard.take_led_update(), None);
+    keyboard.process_key_ev(KeyEvent::new(KeyCode::CapsLock, KeyState::Down));
+    assert_eq!(keyboard.leds(), Leds::NUM_LOCK);
+    assert_eq!(keyboard.take_led_update(), Some(Leds::NUM_LOCK));
+    assert_eq!(set_leds(Leds::empty()), Ok(()));
+}
@@ -0,0 +1,193 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::io::{
+    De105Key, DecodedKey, Dvorak104Key, KeyCode, KeyEvent, KeyState, Keyboard, KeyboardLayout,
+    Uk105Key, Us104Key,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+fn keyboard(layout: &'static dyn KeyboardLayout) -> Keyboard {
+    let mut keyboard = Keyboard::new();
+    keyboard.set_layout(layout);
+    keyboard
+}
+
+fn press(keyboard: &mut Keyboard, code: KeyCode) -> Option<DecodedKey> {
+    let key = keyboard.process_key_ev(KeyEvent::new(code, KeyState::Down));
+    keyboard.process_key_ev(KeyEvent::new(code, KeyState::Up));
+    key
+}
+
+//presses `code` while `modifier` is held
+fn press_with(keyboard: &mut Keyboard, modifier: KeyCode, code: KeyCode) -> Option<DecodedKey> {
+    keyboard.process_key_ev(KeyEvent::new(modifier, KeyState::Down));
+    let key = press(keyboard, code);
+    keyboard.process_key_ev(KeyEvent::new(modifier, KeyState::Up));
+    key
+}
+
+fn unicode(c: char) -> Option<DecodedKey> {
+    Some(DecodedKey::Unicode(c))
+}
+
+#[test_case]
+fn test_us() {
+    let mut keyboard = keyboard(&Us104Key);
+    assert_eq!(press(&mut keyboard, KeyCode::Q), unicode('q'));
+    assert_eq!(press(&mut keyboard, KeyCode::Y), unicode('y'));
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::Key2),
+        unicode('@')
+    );
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftRight, KeyCode::Quote),
+        unicode('"')
+    );
+    assert_eq!(press(&mut keyboard, KeyCode::BackSlash), unicode('\\'));
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ControlLeft, KeyCode::C),
+        unicode('\u{3}')
+    );
+}
+
+#[test_case]
+fn test_uk() {
+    let mut keyboard = keyboard(&Uk105Key);
+    assert_eq!(press(&mut keyboard, KeyCode::Q), unicode('q'));
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::Key2),
+        unicode('"')
+    );
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::Key3),
+        unicode('£')
+    );
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::Quote),
+        unicode('@')
+    );
+    assert_eq!(press(&mut keyboard, KeyCode::BackSlash), unicode('#'));
+    assert_eq!(press(&mut keyboard, KeyCode::Oem102), unicode('\\'));
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::AltRight, KeyCode::Key4),
+        unicode('€')
+    );
+}
+
+#[test_case]
+fn test_german() {
+    let mut keyboard = keyboard(&De105Key);
+    assert_eq!(press(&mut keyboard, KeyCode::Y), unicode('z'));
+    assert_eq!(press(&mut keyboard, KeyCode::Z), unicode('y'));
+    assert_eq!(press(&mut keyboard, KeyCode::Minus), unicode('ß'));
+    assert_eq!(press(&mut keyboard, KeyCode::Quote), unicode('ä'));
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::SemiColon),
+        unicode('Ö')
+    );
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::Key7),
+        unicode('/')
+    );
+    assert_eq!(press(&mut keyboard, KeyCode::Oem102), unicode('<'));
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::AltRight, KeyCode::Q),
+        unicode('@')
+    );
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::AltRight, KeyCode::Key8),
+        unicode('[')
+    );
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::AltRight, KeyCode::E),
+        unicode('€')
+    );
+}
+
+#[test_case]
+fn test_german_caps_lock_umlauts() {
+    let mut keyboard = keyboard(&De105Key);
+    press(&mut keyboard, KeyCode::CapsLock);
+    assert_eq!(
+        press(&mut keyboard, KeyCode::BracketSquareLeft),
+        unicode('Ü')
+    );
+    assert_eq!(press(&mut keyboard, KeyCode::Minus), unicode('ß'));
+    press(&mut keyboard, KeyCode::CapsLock);
+}
+
+#[test_case]
+fn test_dead_keys() {
+    let mut keyboard = keyboard(&De105Key);
+    assert_eq!(press(&mut keyboard, KeyCode::Equals), None);
+    assert_eq!(press(&mut keyboard, KeyCode::E), unicode('é'));
+    assert_eq!(press(&mut keyboard, KeyCode::BackTick), None);
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::O),
+        unicode('Ô')
+    );
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::Equals),
+        None
+    );
+    assert_eq!(press(&mut keyboard, KeyCode::A), unicode('à'));
+    //space and the dead key again give the accent, letters without an accented form follow it
+    assert_eq!(press(&mut keyboard, KeyCode::BackTick), None);
+    assert_eq!(press(&mut keyboard, KeyCode::Spacebar), unicode('^'));
+    assert_eq!(press(&mut keyboard, KeyCode::Equals), None);
+    assert_eq!(press(&mut keyboard, KeyCode::Equals), unicode('´'));
+    assert_eq!(press(&mut keyboard, KeyCode::Equals), None);
+    assert_eq!(press(&mut keyboard, KeyCode::X), unicode('´'));
+    assert_eq!(keyboard.take_pending_key(), unicode('x'));
+    assert_eq!(keyboard.take_pending_key(), None);
+    assert_eq!(press(&mut keyboard, KeyCode::X), unicode('x'));
+}
+
+#[test_case]
+fn test_dvorak() {
+    let mut keyboard = keyboard(&Dvorak104Key);
+    assert_eq!(press(&mut keyboard, KeyCode::Q), unicode('\''));
+    assert_eq!(press(&mut keyboard, KeyCode::R), unicode('p'));
+    assert_eq!(press(&mut keyboard, KeyCode::S), unicode('o'));
+    assert_eq!(press(&mut keyboard, KeyCode::SemiColon), unicode('s'));
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ShiftLeft, KeyCode::Slash),
+        unicode('Z')
+    );
+    assert_eq!(press(&mut keyboard, KeyCode::Minus), unicode('['));
+    assert_eq!(
+        press_with(&mut keyboard, KeyCode::ControlLeft, KeyCode::I),
+        unicode('\u{3}')
+    );
+}
+
+#[test_case]
+fn test_switch_layout() {
+    let mut keyboard = keyboard(&Us104Key);
+    assert_eq!(press(&mut keyboard, KeyCode::Y), unicode('y'));
+    keyboard.set_layout(&De105Key);
+    assert_eq!(press(&mut keyboard, KeyCode::Y), unicode('z'));
+    //a pending dead key doesn't survive the switch
+    press(&mut keyboard, KeyCode::Equals);
+    keyboard.set_layout(&Dvorak104Key);
+    assert_eq!(press(&mut keyboard, KeyCode::D), unicode('e'));
+}
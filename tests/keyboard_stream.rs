@@ -0,0 +1,144 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::io::{
+    add_scancode, is_pressed, set_key_repeat, DecodedKey, KeyCode, KeyEvent, KeyRepeat, KeyState,
+    KeyboardEvent, KeyboardStream,
+};
+use finn_os::timer;
+use futures_util::future::FutureExt;
+use futures_util::stream::StreamExt;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+//the next event if there is one already
+fn next(stream: &mut KeyboardStream) -> Option<KeyboardEvent> {
+    stream.next().now_or_never().flatten()
+}
+
+#[test_case]
+fn test_every_subscriber_gets_every_event() {
+    let mut first = KeyboardStream::new();
+    let mut second = KeyboardStream::new();
+    add_scancode(0x1E);
+    add_scancode(0x9E);
+    for stream in [&mut first, &mut second] {
+        let event = next(stream).unwrap();
+        assert_eq!(event.event, KeyEvent::new(KeyCode::A, KeyState::Down));
+        assert_eq!(event.key, Some(DecodedKey::Unicode('a')));
+        assert!(!event.repeat);
+        let event = next(stream).unwrap();
+        assert_eq!(event.event, KeyEvent::new(KeyCode::A, KeyState::Up));
+        assert_eq!(event.char(), None);
+        assert!(next(stream).is_none());
+    }
+}
+
+#[test_case]
+fn test_events_before_subscribing_are_not_delivered() {
+    add_scancode(0x30);
+    add_scancode(0xB0);
+    let mut stream = KeyboardStream::new();
+    assert!(next(&mut stream).is_none());
+}
+
+#[test_case]
+fn test_modifier_snapshot() {
+    let mut stream = KeyboardStream::new();
+    add_scancode(0x2A); //left shift
+    add_scancode(0x1E);
+    add_scancode(0x9E);
+    add_scancode(0xAA);
+    let shift = next(&mut stream).unwrap();
+    assert!(shift.modifiers.lshift);
+    assert_eq!(shift.key, None);
+    let a = next(&mut stream).unwrap();
+    assert!(a.modifiers.is_shifted());
+    assert_eq!(a.char(), Some('A'));
+    next(&mut stream);
+    assert!(!next(&mut stream).unwrap().modifiers.is_shifted());
+}
+
+#[test_case]
+fn test_pressed_keys() {
+    add_scancode(0x11);
+    add_scancode(0xE0);
+    add_scancode(0x48);
+    assert!(is_pressed(KeyCode::W));
+    assert!(is_pressed(KeyCode::ArrowUp));
+    assert!(!is_pressed(KeyCode::Numpad8));
+    add_scancode(0x91);
+    add_scancode(0xE0);
+    add_scancode(0xC8);
+    assert!(!is_pressed(KeyCode::W));
+    assert!(!is_pressed(KeyCode::ArrowUp));
+}
+
+#[test_case]
+fn test_hardware_repeat_is_ignored() {
+    set_key_repeat(None);
+    let mut stream = KeyboardStream::new();
+    add_scancode(0x1F);
+    add_scancode(0x1F);
+    add_scancode(0x1F);
+    add_scancode(0x9F);
+    assert_eq!(next(&mut stream).unwrap().event.state, KeyState::Down);
+    assert_eq!(next(&mut stream).unwrap().event.state, KeyState::Up);
+    assert!(next(&mut stream).is_none());
+    set_key_repeat(Some(KeyRepeat::DEFAULT));
+}
+
+#[test_case]
+fn test_software_repeat() {
+    set_key_repeat(Some(KeyRepeat {
+        delay: 2,
+        interval: 3,
+    }));
+    let mut stream = KeyboardStream::new();
+    add_scancode(0x20);
+    let pressed_at = timer::ticks();
+    assert!(!next(&mut stream).unwrap().repeat);
+    while timer::ticks() < pressed_at + 8 {
+        x86_64::instructions::hlt();
+    }
+    let repeat = next(&mut stream).unwrap();
+    assert!(repeat.repeat);
+    assert_eq!(repeat.char(), Some('d'));
+    //the repeats missed while nobody was reading aren't made up for
+    let repeated_at = timer::ticks();
+    assert!(next(&mut stream).is_none());
+    while timer::ticks() < repeated_at + 3 {
+        x86_64::instructions::hlt();
+    }
+    assert!(next(&mut stream).unwrap().repeat);
+    //releasing the key stops it
+    add_scancode(0xA0);
+    while let Some(event) = next(&mut stream) {
+        if event.event.state == KeyState::Up {
+            break;
+        }
+    }
+    let released_at = timer::ticks();
+    while timer::ticks() < released_at + 3 {
+        x86_64::instructions::hlt();
+    }
+    assert!(next(&mut stream).is_none());
+    set_key_repeat(Some(KeyRepeat::DEFAULT));
+}
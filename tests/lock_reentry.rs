@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use finn_os::sync::IrqSafeMutex;
+use finn_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+static LOCK: IrqSafeMutex<()> = IrqSafeMutex::new("reentered_lock", ());
+
+entry_point!(main);
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("lock_reentry::lock_twice...\t");
+    //the check only exists with debug assertions
+    if !cfg!(debug_assertions) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    }
+    let _first = LOCK.lock();
+    let _second = LOCK.lock();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+//the panic message is formatted into this since there is no heap
+struct Buffer {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = (self.len + s.len()).min(self.bytes.len());
+        self.bytes[self.len..end].copy_from_slice(&s.as_bytes()[..end - self.len]);
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Buffer {
+        bytes: [0; 256],
+        len: 0,
+    };
+    let _ = write!(message, "{}", info);
+    let message = core::str::from_utf8(&message.bytes[..message.len]).unwrap_or("");
+    if message.contains("lock reentered_lock re-entered") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n\nError: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
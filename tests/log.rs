@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::log::{self, Level, LevelFilter, Record, Sink};
+use finn_os::{debug, error, info, io, trace, warn};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+mod inner {
+    pub fn log_debug(message: &str) {
+        finn_os::debug!("{}", message);
+    }
+}
+
+//collects what it gets so the tests can look at the records
+struct Collect(Arc<Mutex<Vec<(Level, String)>>>);
+
+impl Sink for Collect {
+    fn log(&mut self, record: &Record) {
+        use core::fmt::Write;
+        let mut message = String::new();
+        write!(message, "{}", record.args).unwrap();
+        self.0.lock().push((record.level, message));
+    }
+}
+
+#[test_case]
+fn test_serial_and_ring_buffer() {
+    io::start_capture();
+    warn!("disk {} is on fire", 3);
+    let output = io::stop_capture();
+    assert!(output.contains("WARN  log: disk 3 is on fire\n"));
+    //every line starts with its tick timestamp
+    assert!(output.starts_with('['));
+    assert!(log::ring_buffer_contents().contains("WARN  log: disk 3 is on fire\n"));
+}
+
+#[test_case]
+fn test_levels() {
+    let records = Arc::new(Mutex::new(Vec::new()));
+    log::add_sink(Box::new(Collect(records.clone())));
+
+    error!("error");
+    warn!("warn");
+    info!("info");
+    debug!("debug");
+    trace!("trace");
+    let levels: Vec<Level> = records.lock().iter().map(|(level, _)| *level).collect();
+    assert!(levels.ends_with(&[Level::Error, Level::Warn, Level::Info]));
+
+    log::set_level(LevelFilter::Trace);
+    records.lock().clear();
+    trace!("trace");
+    log::set_level(LevelFilter::Info);
+    assert_eq!(
+        records.lock().as_slice(),
+        &[(Level::Trace, String::from("trace"))]
+    );
+}
+
+#[test_case]
+fn test_module_filters() {
+    assert!(!log::enabled(Level::Debug, "log::inner"));
+    log::set_module_level("log::inner", LevelFilter::Debug);
+    assert!(log::enabled(Level::Debug, "log::inner"));
+    assert!(log::enabled(Level::Debug, "log::inner::deeper"));
+    //only whole module names match
+    assert!(!log::enabled(Level::Debug, "log::innermost"));
+    assert!(!log::enabled(Level::Debug, "log"));
+
+    io::start_capture();
+    inner::log_debug("shown");
+    let output = io::stop_capture();
+    assert!(output.contains("DEBUG log::inner: shown"));
+
+    //the longer prefix wins
+    log::set_module_level("log", LevelFilter::Off);
+    assert!(log::enabled(Level::Debug, "log::inner"));
+    assert!(!log::enabled(Level::Error, "log"));
+
+    log::clear_module_level("log");
+    log::clear_module_level("log::inner");
+    assert!(!log::enabled(Level::Debug, "log::inner"));
+}
+
+#[test_case]
+fn test_dump_ring_buffer() {
+    info!("remember this");
+    log::dump_ring_buffer();
+    //a panic while the port is locked must not hang on it
+    let serial = io::SERIAL.lock();
+    log::dump_ring_buffer();
+    drop(serial);
+    assert!(log::ring_buffer_contents().contains("INFO  log: remember this"));
+}
@@ -0,0 +1,203 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::io::{
+    set_resolution, set_sample_rate, set_scaling, Acceleration, Mouse, MouseButton,
+    MouseButtonEvent, MouseKind, Resolution, SampleRate, Scaling, MOUSE,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+fn feed(mouse: &mut Mouse, packet: &[u8]) {
+    for byte in packet {
+        mouse.process_packet(*byte);
+    }
+}
+
+//QEMU's mouse answers both IntelliMouse sequences
+#[test_case]
+fn test_detects_five_button_mouse() {
+    let mouse = MOUSE.lock();
+    assert!(mouse.is_present());
+    assert_eq!(mouse.kind(), MouseKind::FiveButton);
+}
+
+#[test_case]
+fn test_new_mouse_is_not_present() {
+    assert!(!Mouse::new().is_present());
+}
+
+#[test_case]
+fn test_deltas_accumulate_between_reads() {
+    let mut mouse = Mouse::new();
+    feed(&mut mouse, &[0x08, 5, 3]);
+    feed(&mut mouse, &[0x08 | 0x10, 0xFE, 4]);
+    assert_eq!(mouse.get_coords(), (3, 7));
+    assert_eq!(mouse.get_coords(), (0, 0));
+}
+
+#[test_case]
+fn test_cursor_is_clamped() {
+    let mut mouse = Mouse::new();
+    mouse.set_position(10, 10);
+    //y grows downwards on the screen but upwards for the mouse
+    feed(&mut mouse, &[0x08, 5, 3]);
+    assert_eq!(mouse.position(), (15, 7));
+    feed(&mut mouse, &[0x08 | 0x10 | 0x20, 0x80, 0x80]);
+    assert_eq!(mouse.position(), (0, 135));
+    feed(&mut mouse, &[0x08 | 0x20, 0x7F, 0x80]);
+    feed(&mut mouse, &[0x08 | 0x20, 0x7F, 0x80]);
+    feed(&mut mouse, &[0x08 | 0x20, 0x7F, 0x80]);
+    assert_eq!(mouse.position(), (319, 199));
+    mouse.set_screen_size(100, 50);
+    assert_eq!(mouse.position(), (99, 49));
+}
+
+#[test_case]
+fn test_overflow_saturates() {
+    let mut mouse = Mouse::new();
+    feed(&mut mouse, &[0x08 | 0x40 | 0x80 | 0x20, 0x12, 0x34]);
+    assert_eq!(mouse.get_coords(), (255, -256));
+}
+
+#[test_case]
+fn test_button_events() {
+    let mut mouse = Mouse::new();
+    mouse.set_position(20, 30);
+    feed(&mut mouse, &[0x08 | 0x01, 0, 0]);
+    feed(&mut mouse, &[0x08 | 0x01 | 0x04, 0, 0]);
+    assert!(mouse.state().middle_button_down());
+    feed(&mut mouse, &[0x08, 0, 0]);
+    let event = |button, pressed| {
+        Some(MouseButtonEvent {
+            button,
+            pressed,
+            x: 20,
+            y: 30,
+        })
+    };
+    assert_eq!(mouse.pop_button_event(), event(MouseButton::Left, true));
+    assert_eq!(mouse.pop_button_event(), event(MouseButton::Middle, true));
+    assert_eq!(mouse.pop_button_event(), event(MouseButton::Left, false));
+    assert_eq!(mouse.pop_button_event(), event(MouseButton::Middle, false));
+    assert_eq!(mouse.pop_button_event(), None);
+}
+
+#[test_case]
+fn test_packets_without_always_one_are_skipped() {
+    let mut mouse = Mouse::new();
+    feed(&mut mouse, &[0x00, 0x08, 1, 2]);
+    assert_eq!(mouse.get_coords(), (1, 2));
+}
+
+#[test_case]
+fn test_wheel() {
+    let mut mouse = Mouse::new();
+    mouse.set_kind(MouseKind::Wheel);
+    feed(&mut mouse, &[0x08, 1, 0, 0xFF]);
+    feed(&mut mouse, &[0x08, 1, 0, 0xFE]);
+    assert_eq!(mouse.take_scroll(), -3);
+    assert_eq!(mouse.take_scroll(), 0);
+    assert_eq!(mouse.get_coords(), (2, 0));
+}
+
+#[test_case]
+fn test_five_buttons() {
+    let mut mouse = Mouse::new();
+    mouse.set_kind(MouseKind::FiveButton);
+    feed(&mut mouse, &[0x08, 0, 0, 0x10 | 0x01]);
+    assert!(mouse.state().button_down(MouseButton::Button4));
+    assert_eq!(mouse.take_scroll(), 1);
+    feed(&mut mouse, &[0x08, 0, 0, 0x20 | 0x0F]);
+    assert!(!mouse.state().button_down(MouseButton::Button4));
+    assert!(mouse.state().button_down(MouseButton::Button5));
+    assert_eq!(mouse.take_scroll(), -1);
+    assert_eq!(
+        mouse
+            .pop_button_event()
+            .map(|event| (event.button, event.pressed)),
+        Some((MouseButton::Button4, true))
+    );
+    assert_eq!(
+        mouse
+            .pop_button_event()
+            .map(|event| (event.button, event.pressed)),
+        Some((MouseButton::Button4, false))
+    );
+    assert_eq!(
+        mouse
+            .pop_button_event()
+            .map(|event| (event.button, event.pressed)),
+        Some((MouseButton::Button5, true))
+    );
+}
+
+#[test_case]
+fn test_gap_resyncs_packet() {
+    let mut mouse = Mouse::new();
+    //the y byte of the first packet got lost
+    mouse.process_packet_at(0x08, 10);
+    mouse.process_packet_at(7, 10);
+    for byte in [0x08, 1, 2] {
+        mouse.process_packet_at(byte, 12);
+    }
+    assert_eq!(mouse.get_coords(), (1, 2));
+}
+
+#[test_case]
+fn test_bytes_within_a_tick_stay_together() {
+    let mut mouse = Mouse::new();
+    mouse.process_packet_at(0x08, 10);
+    mouse.process_packet_at(3, 11);
+    mouse.process_packet_at(4, 11);
+    assert_eq!(mouse.get_coords(), (3, 4));
+}
+
+#[test_case]
+fn test_hardware_settings_are_acknowledged() {
+    assert_eq!(set_sample_rate(SampleRate::Hz200), Ok(()));
+    assert_eq!(set_resolution(Resolution::CountsPerMm8), Ok(()));
+    assert_eq!(set_scaling(Scaling::TwoToOne), Ok(()));
+    assert_eq!(set_scaling(Scaling::OneToOne), Ok(()));
+    assert_eq!(set_resolution(Resolution::CountsPerMm4), Ok(()));
+    assert_eq!(set_sample_rate(SampleRate::Hz100), Ok(()));
+}
+
+#[test_case]
+fn test_sensitivity_scales_motion() {
+    let mut mouse = Mouse::new();
+    mouse.set_sensitivity(0.5);
+    feed(&mut mouse, &[0x08, 4, 2]);
+    assert_eq!(mouse.take_motion(), (2.0, 1.0));
+    assert_eq!(mouse.take_motion(), (0.0, 0.0));
+    //the raw deltas and the cursor aren't affected
+    assert_eq!(mouse.get_coords(), (4, 2));
+}
+
+#[test_case]
+fn test_acceleration_above_threshold() {
+    let mut mouse = Mouse::new();
+    mouse.set_acceleration(Some(Acceleration::DEFAULT));
+    feed(&mut mouse, &[0x08, 2, 1]);
+    assert_eq!(mouse.take_motion(), (2.0, 1.0));
+    //8 counts, the 4 above the threshold count double
+    feed(&mut mouse, &[0x08, 6, 2]);
+    assert_eq!(mouse.take_motion(), (9.0, 3.0));
+}
@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use finn_os::elf::{HELLO, SPIN};
+use finn_os::process::{self, ExitStatus, ProcessState};
+use finn_os::{memory, thread};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+fn used_frames() -> usize {
+    memory::with_frame_allocator(|frame_allocator| frame_allocator.used_frames())
+}
+
+#[test_case]
+fn test_exit_code() {
+    let pid = process::spawn(HELLO, &["a", "b", "c"]).expect("spawn failed");
+    assert_eq!(process::wait(pid), Some(ExitStatus::Exited(3)));
+    //nobody can wait twice
+    assert_eq!(process::wait(pid), None);
+    assert_eq!(process::state(pid), None);
+}
+
+#[test_case]
+fn test_kill() {
+    let pid = process::spawn(SPIN, &[]).expect("spawn failed");
+    thread::sleep(2);
+    assert_eq!(process::state(pid), Some(ProcessState::Running));
+
+    assert!(process::kill(pid));
+    assert_eq!(
+        process::state(pid),
+        Some(ProcessState::Zombie(ExitStatus::Killed))
+    );
+    assert!(!process::kill(pid));
+    assert_eq!(process::wait(pid), Some(ExitStatus::Killed));
+}
+
+#[test_case]
+fn test_processes_run_side_by_side() {
+    let spinners = [
+        process::spawn(SPIN, &[]).expect("spawn failed"),
+        process::spawn(SPIN, &[]).expect("spawn failed"),
+    ];
+    //both use the same user addresses, each in its own address space
+    let hello = process::spawn(HELLO, &["x"]).expect("spawn failed");
+    assert_eq!(process::wait(hello), Some(ExitStatus::Exited(1)));
+    for pid in spinners {
+        assert!(process::kill(pid));
+        assert_eq!(process::wait(pid), Some(ExitStatus::Killed));
+    }
+}
+
+#[test_case]
+fn test_frames_are_freed() {
+    let before = used_frames();
+    for _ in 0..20 {
+        let pid = process::spawn(HELLO, &["hello"]).expect("spawn failed");
+        process::wait(pid);
+        let pid = process::spawn(SPIN, &[]).expect("spawn failed");
+        process::kill(pid);
+        process::wait(pid);
+    }
+    assert_eq!(used_frames(), before);
+}
@@ -0,0 +1,135 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use finn_os::executor::{Executor, Task};
+use finn_os::io::{
+    start_capture, stop_capture, ComPort, Parity, SerialConfig, SerialReservation, SerialStream,
+    StopBits, WordLength, DEBUG_SERIAL, SERIAL,
+};
+use finn_os::serial_print;
+use futures_util::stream::StreamExt;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_receive_in_loopback() {
+    static CHECKED: AtomicBool = AtomicBool::new(false);
+    const MESSAGE: &[u8] = b"ping";
+
+    //nothing printed in between reaches the host until loopback is switched off again
+    {
+        let mut serial = SERIAL.lock();
+        serial.set_loopback(true);
+        for byte in MESSAGE {
+            serial.send(*byte);
+        }
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let received: Vec<u8> = SerialStream::new().take(MESSAGE.len()).collect().await;
+        SERIAL.lock().set_loopback(false);
+        assert_eq!(received, MESSAGE);
+        CHECKED.store(true, Ordering::SeqCst);
+    }));
+    executor.test_run();
+    assert!(CHECKED.load(Ordering::SeqCst));
+}
+
+//the tests run with COM1 on stdio and COM2 on null, see Cargo.toml
+#[test_case]
+fn test_detected_ports() {
+    assert!(ComPort::Com1.is_present());
+    assert!(ComPort::Com2.is_present());
+    assert!(!ComPort::Com3.is_present());
+    assert!(!ComPort::Com4.is_present());
+    assert!(DEBUG_SERIAL.lock().is_some());
+}
+
+#[test_case]
+fn test_line_settings() {
+    assert_eq!(SerialConfig::default().divisor(), 3);
+    assert_eq!(SerialConfig::default().line_ctrl(), 0b0000_0011);
+
+    let config = SerialConfig {
+        baud: 9600,
+        word_length: WordLength::Seven,
+        parity: Parity::Even,
+        stop_bits: StopBits::Two,
+    };
+    assert_eq!(config.divisor(), 12);
+    assert_eq!(config.line_ctrl(), 0b0001_1110);
+
+    let config = SerialConfig {
+        baud: 115200,
+        word_length: WordLength::Five,
+        parity: Parity::Space,
+        stop_bits: StopBits::One,
+    };
+    assert_eq!(config.divisor(), 1);
+    assert_eq!(config.line_ctrl(), 0b0011_1000);
+}
+
+#[test_case]
+fn test_reconfigured_debug_port() {
+    let mut debug_serial = DEBUG_SERIAL.lock();
+    let port = debug_serial.as_mut().expect("no COM2");
+    let config = SerialConfig {
+        baud: 9600,
+        word_length: WordLength::Seven,
+        parity: Parity::Odd,
+        stop_bits: StopBits::One,
+    };
+    port.configure(config);
+    assert_eq!(port.config(), config);
+    assert!(port.self_test());
+
+    port.set_loopback(true);
+    port.send(b'A');
+    let received = (0..1000).find_map(|_| port.receive());
+    port.set_loopback(false);
+    port.configure(SerialConfig::default());
+    assert_eq!(received, Some(b'A'));
+}
+
+#[test_case]
+fn test_reservation() {
+    let reservation = SerialReservation::acquire().expect("COM1 is reserved");
+    assert!(SerialReservation::acquire().is_none());
+    //text printed meanwhile doesn't go out, but is still captured
+    start_capture();
+    serial_print!("held back");
+    assert_eq!(stop_capture(), "held back");
+    drop(reservation);
+    assert!(SerialReservation::acquire().is_some());
+}
+
+#[test_case]
+fn test_cancel_reservation() {
+    let reservation = SerialReservation::acquire().expect("COM1 is reserved");
+    serial_print!("held back ");
+    SerialReservation::cancel();
+    assert!(SerialReservation::acquire().is_some());
+    drop(reservation);
+}
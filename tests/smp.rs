@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use finn_os::{smp, thread, timer};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+//the tests run with -smp 4, see Cargo.toml
+const CPUS: usize = 4;
+
+#[test_case]
+fn test_all_cpus_started() {
+    assert_eq!(smp::cpu_count(), CPUS);
+    for cpu in 0..CPUS {
+        assert!(smp::cpu(cpu).is_online());
+    }
+}
+
+#[test_case]
+fn test_application_processors_get_timer_interrupts() {
+    thread::sleep(3);
+    for cpu in 1..CPUS {
+        assert!(smp::cpu(cpu).local_ticks.load(Ordering::Relaxed) > 0);
+    }
+}
+
+#[test_case]
+fn test_threads_run_on_every_cpu() {
+    static SEEN_ON: AtomicU32 = AtomicU32::new(0);
+
+    //busy threads, so every cpu has something to pick up
+    let threads: Vec<_> = (0..CPUS * 2)
+        .map(|_| {
+            thread::spawn(|| {
+                let end = timer::ticks() + 5;
+                while timer::ticks() < end {
+                    SEEN_ON.fetch_or(1 << smp::current_cpu(), Ordering::Relaxed);
+                }
+            })
+        })
+        .collect();
+    for id in threads {
+        thread::join(id);
+    }
+    assert_eq!(SEEN_ON.load(Ordering::Relaxed), (1 << CPUS) - 1);
+}
+
+#[test_case]
+fn test_parallel_counter() {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    const THREADS: usize = 16;
+    const INCREMENTS: usize = 10_000;
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            thread::spawn(|| {
+                for _ in 0..INCREMENTS {
+                    COUNTER.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        })
+        .collect();
+    for id in threads {
+        thread::join(id);
+    }
+    assert_eq!(COUNTER.load(Ordering::Relaxed), THREADS * INCREMENTS);
+}
@@ -4,10 +4,20 @@
 #![test_runner(finn_os::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+use alloc::vec::Vec;
 use bootloader::{entry_point, BootInfo};
+use core::future::{pending, Future};
 use core::panic::PanicInfo;
-use finn_os::executor::{Executor, Task};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use finn_os::executor::sync::{self, mpsc, oneshot};
+use finn_os::executor::{self, Executor, JoinError, Priority, Task};
+use finn_os::io::{start_capture, stop_capture};
 use finn_os::serial_print;
+use spin::Mutex;
 
 entry_point!(main);
 
@@ -33,3 +43,295 @@ fn test_executor() {
     executor.spawn(Task::new(example_task()));
     executor.test_run()
 }
+
+#[test_case]
+fn test_join_handle_output() {
+    static CHECKED: AtomicBool = AtomicBool::new(false);
+
+    async fn answer() -> u32 {
+        42
+    }
+
+    let mut executor = Executor::new();
+    let handle = executor.spawn(Task::new(answer()));
+    executor.spawn(Task::new(async move {
+        assert_eq!(handle.await, Ok(42));
+        CHECKED.store(true, Ordering::SeqCst);
+    }));
+    executor.test_run();
+    assert!(CHECKED.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn test_join_handle_abort() {
+    static CHECKED: AtomicBool = AtomicBool::new(false);
+
+    let mut executor = Executor::new();
+    let never = executor.spawn(Task::new(pending::<()>()));
+    executor.spawn(Task::new(async move {
+        never.abort();
+        assert_eq!(never.await, Err(JoinError::Aborted));
+        CHECKED.store(true, Ordering::SeqCst);
+    }));
+    executor.test_run();
+    assert!(CHECKED.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn test_task_spawns_task() {
+    static CHECKED: AtomicBool = AtomicBool::new(false);
+
+    async fn child(n: u32) -> u32 {
+        n * 2
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let handle = executor::spawn(Task::new(child(21)));
+        assert_eq!(handle.await, Ok(42));
+        CHECKED.store(true, Ordering::SeqCst);
+    }));
+    executor.test_run();
+    assert!(CHECKED.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn test_spawner_handle() {
+    static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+    async fn leaf() {
+        SPAWNED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+        //each level spawns the next one through a clone of the same spawner
+        let inner = spawner.clone();
+        spawner.spawn(Task::new(async move {
+            inner.spawn(Task::new(leaf()));
+            leaf().await;
+        }));
+    }));
+    executor.test_run();
+    assert_eq!(SPAWNED.load(Ordering::SeqCst), 2);
+}
+
+//Every task waits for all other tasks each round, the last one to arrive wakes everybody at once
+#[test_case]
+fn test_wake_storm() {
+    const TASKS: usize = 2000;
+    const ROUNDS: usize = 3;
+
+    static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+    static GENERATION: AtomicUsize = AtomicUsize::new(0);
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+    static WAKERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+
+    struct NextGeneration(usize);
+
+    impl Future for NextGeneration {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            let mut wakers = WAKERS.lock();
+            if GENERATION.load(Ordering::SeqCst) > self.0 {
+                return Poll::Ready(());
+            }
+            wakers.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    async fn worker() {
+        for round in 0..ROUNDS {
+            if ARRIVED.fetch_add(1, Ordering::SeqCst) + 1 == TASKS * (round + 1) {
+                GENERATION.store(round + 1, Ordering::SeqCst);
+                for waker in WAKERS.lock().drain(..) {
+                    waker.wake();
+                }
+            } else {
+                NextGeneration(round).await;
+            }
+        }
+        FINISHED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    //a queue much smaller than the number of tasks that get woken at once
+    let mut executor = Executor::with_capacity(32);
+    for _ in 0..TASKS {
+        executor.spawn(Task::new(worker()));
+    }
+    executor.test_run();
+    assert_eq!(FINISHED.load(Ordering::SeqCst), TASKS);
+}
+
+#[test_case]
+fn test_low_priority_progress() {
+    static LOW_RAN: AtomicBool = AtomicBool::new(false);
+    static HIGH_POLLS: AtomicUsize = AtomicUsize::new(0);
+
+    //Keeps re-waking itself like a busy renderer until the low priority task got a turn
+    async fn busy() {
+        while !LOW_RAN.load(Ordering::SeqCst) {
+            HIGH_POLLS.fetch_add(1, Ordering::SeqCst);
+            executor::yield_now().await;
+        }
+    }
+
+    async fn background() {
+        LOW_RAN.store(true, Ordering::SeqCst);
+    }
+
+    let mut executor = Executor::new();
+    for _ in 0..4 {
+        executor.spawn(Task::new(busy()).with_priority(Priority::High));
+    }
+    executor.spawn(Task::new(background()).with_priority(Priority::Low));
+    executor.test_run();
+    assert!(LOW_RAN.load(Ordering::SeqCst));
+    //the low priority task has to run within the first round
+    assert!(HIGH_POLLS.load(Ordering::SeqCst) <= 8);
+}
+
+#[test_case]
+fn test_async_mutex() {
+    static COUNTER: sync::Mutex<usize> = sync::Mutex::new(0);
+
+    //yielding while holding the lock gives the other tasks a chance to run into it
+    async fn increment() {
+        for _ in 0..10 {
+            let mut counter = COUNTER.lock().await;
+            let value = *counter;
+            executor::yield_now().await;
+            *counter = value + 1;
+        }
+    }
+
+    let mut executor = Executor::new();
+    for _ in 0..5 {
+        executor.spawn(Task::new(increment()));
+    }
+    executor.test_run();
+    assert_eq!(*COUNTER.try_lock().unwrap(), 50);
+}
+
+#[test_case]
+fn test_semaphore() {
+    static SEMAPHORE: sync::Semaphore = sync::Semaphore::new(2);
+    static HOLDERS: AtomicUsize = AtomicUsize::new(0);
+    static MAX_HOLDERS: AtomicUsize = AtomicUsize::new(0);
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+
+    async fn worker() {
+        let _permit = SEMAPHORE.acquire().await;
+        let holders = HOLDERS.fetch_add(1, Ordering::SeqCst) + 1;
+        MAX_HOLDERS.fetch_max(holders, Ordering::SeqCst);
+        executor::yield_now().await;
+        HOLDERS.fetch_sub(1, Ordering::SeqCst);
+        DONE.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let mut executor = Executor::new();
+    for _ in 0..6 {
+        executor.spawn(Task::new(worker()));
+    }
+    executor.test_run();
+    assert_eq!(DONE.load(Ordering::SeqCst), 6);
+    assert_eq!(MAX_HOLDERS.load(Ordering::SeqCst), 2);
+    assert_eq!(SEMAPHORE.available_permits(), 2);
+}
+
+#[test_case]
+fn test_oneshot() {
+    static CHECKED: AtomicUsize = AtomicUsize::new(0);
+
+    let (sender, receiver) = oneshot::channel();
+    let (dropped_sender, dropped_receiver) = oneshot::channel::<u32>();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        assert_eq!(receiver.await, Ok(7));
+        CHECKED.fetch_add(1, Ordering::SeqCst);
+    }));
+    executor.spawn(Task::new(async move {
+        assert_eq!(dropped_receiver.await, Err(oneshot::RecvError));
+        CHECKED.fetch_add(1, Ordering::SeqCst);
+    }));
+    executor.spawn(Task::new(async move {
+        executor::yield_now().await;
+        sender.send(7).unwrap();
+        drop(dropped_sender);
+    }));
+    executor.test_run();
+    assert_eq!(CHECKED.load(Ordering::SeqCst), 2);
+}
+
+#[test_case]
+fn test_mpsc() {
+    static SUM: AtomicUsize = AtomicUsize::new(0);
+    static CLOSED: AtomicBool = AtomicBool::new(false);
+
+    //a small capacity makes the producers wait for the consumer
+    let (sender, mut receiver) = mpsc::channel(4);
+
+    let mut executor = Executor::new();
+    for producer in 0..3 {
+        let sender = sender.clone();
+        executor.spawn(Task::new(async move {
+            for i in 0..100 {
+                sender.send(producer * 100 + i).await.unwrap();
+            }
+        }));
+    }
+    drop(sender);
+    executor.spawn(Task::new(async move {
+        while let Some(value) = receiver.recv().await {
+            SUM.fetch_add(value, Ordering::SeqCst);
+        }
+        CLOSED.store(true, Ordering::SeqCst);
+    }));
+    executor.test_run();
+    assert_eq!(SUM.load(Ordering::SeqCst), (0..300).sum::<usize>());
+    assert!(CLOSED.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn test_dump() {
+    let (sender, receiver) = oneshot::channel::<()>();
+    let mut executor = Executor::new();
+    let waiting = executor.spawn(Task::new(pending::<()>()).with_name("waiting"));
+    let woken = executor.spawn(
+        Task::new(async move {
+            executor::yield_now().await;
+            let _ = receiver.await;
+            pending::<()>().await
+        })
+        .with_name("woken"),
+    );
+    executor.test_run();
+    //queued again, but not polled yet
+    sender.send(()).unwrap();
+    start_capture();
+    executor.dump();
+    let output = stop_capture();
+    let row = |name| -> Vec<&str> {
+        output
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .find(|fields| fields.get(1) == Some(&name))
+            .unwrap()
+    };
+    //id, name, priority, ready (empty if not), polls
+    let waiting_row = row("waiting");
+    assert_eq!(waiting_row[2], "normal");
+    assert_eq!(waiting_row[3], "1");
+    let woken_row = row("woken");
+    assert_eq!(woken_row[2], "normal");
+    assert_eq!(woken_row[3], "*");
+    assert_eq!(woken_row[4], "2");
+    assert!(output.contains("2 tasks"));
+    waiting.abort();
+    woken.abort();
+    executor.test_run();
+}
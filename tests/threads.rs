@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use finn_os::executor::{Executor, Task};
+use finn_os::{thread, timer};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_spawn_and_yield() {
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+    for _ in 0..3 {
+        thread::spawn(|| {
+            for _ in 0..10 {
+                thread::yield_now();
+            }
+            FINISHED.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    while FINISHED.load(Ordering::SeqCst) < 3 {
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn test_preemption() {
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static SPINS: AtomicUsize = AtomicUsize::new(0);
+
+    //never yields - only the timer can take the cpu away from it
+    thread::spawn(|| {
+        while !STOP.load(Ordering::SeqCst) {
+            SPINS.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    while SPINS.load(Ordering::SeqCst) == 0 {
+        core::hint::spin_loop();
+    }
+    STOP.store(true, Ordering::SeqCst);
+}
+
+#[test_case]
+fn test_sleep() {
+    let start = timer::ticks();
+    thread::sleep(3);
+    assert!(timer::ticks() >= start + 3);
+}
+
+#[test_case]
+fn test_executor_thread() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    async fn task() {
+        timer::sleep(1).await;
+        DONE.store(true, Ordering::SeqCst);
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(task()));
+    thread::spawn(move || executor.run());
+    while !DONE.load(Ordering::SeqCst) {
+        thread::yield_now();
+    }
+}
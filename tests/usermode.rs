@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(finn_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::ptr::copy_nonoverlapping;
+use finn_os::io::{start_capture, stop_capture};
+use finn_os::thread;
+use finn_os::usermode::{self, USER_SPACE_START};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    finn_os::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    finn_os::test_panic_handler(info)
+}
+
+//    mov eax, 0              ; write
+//    lea rdi, [rip + msg]
+//    mov esi, msg_end - msg
+//    int 0x80
+//    mov ebx, eax
+//    mov eax, 3              ; yield
+//    int 0x80
+//    mov eax, 2              ; sleep(1)
+//    mov edi, 1
+//    int 0x80
+//    mov edi, ebx
+//    mov eax, 1              ; exit(bytes written)
+//    int 0x80
+//    ud2
+// msg: .ascii "hello from ring 3\n"
+const PROGRAM: [u8; 69] = [
+    0xb8, 0x00, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x3d, 0x27, 0x00, 0x00, 0x00, 0xbe, 0x12, 0x00, 0x00,
+    0x00, 0xcd, 0x80, 0x89, 0xc3, 0xb8, 0x03, 0x00, 0x00, 0x00, 0xcd, 0x80, 0xb8, 0x02, 0x00, 0x00,
+    0x00, 0xbf, 0x01, 0x00, 0x00, 0x00, 0xcd, 0x80, 0x89, 0xdf, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xcd,
+    0x80, 0x0f, 0x0b, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x66, 0x72, 0x6f, 0x6d, 0x20, 0x72, 0x69,
+    0x6e, 0x67, 0x20, 0x33, 0x0a,
+];
+
+#[test_case]
+fn test_user_program() {
+    const STACK_SIZE: u64 = 4096;
+
+    let code = VirtAddr::new(USER_SPACE_START);
+    let stack = VirtAddr::new(USER_SPACE_START + 0x10_0000);
+    usermode::map_user_region(code, PROGRAM.len() as u64, PageTableFlags::WRITABLE).unwrap();
+    usermode::map_user_region(stack, STACK_SIZE, PageTableFlags::WRITABLE).unwrap();
+    unsafe {
+        copy_nonoverlapping(PROGRAM.as_ptr(), code.as_mut_ptr(), PROGRAM.len());
+    }
+
+    start_capture();
+    let user = thread::spawn(move || usermode::enter(code, stack + STACK_SIZE));
+    thread::join(user);
+    assert_eq!(stop_capture(), "hello from ring 3\n");
+}
+
+#[test_case]
+fn test_user_range() {
+    assert!(usermode::is_user_range(USER_SPACE_START, 4096));
+    assert!(!usermode::is_user_range(0, 4096));
+    assert!(!usermode::is_user_range(u64::MAX - 10, 100));
+    //kernel memory must not be readable through syscalls
+    assert!(!usermode::is_user_readable(main as *const () as u64, 1));
+}